@@ -0,0 +1,469 @@
+use super::PlayerBackend;
+use crate::capabilities::Capabilities;
+use crate::error::{BridgeError, Result};
+use crate::snapshot::{StatusSnapshot, TrackSnapshot};
+use crate::status::{PlaybackStatus, RepeatMode};
+use crate::uri::SpotifyUri;
+use std::collections::HashMap;
+use std::io::{self, Error, ErrorKind, Read};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+
+const QUERIES: &[&str] = &[
+    "status",
+    "state",
+    "shuffling",
+    "repeat",
+    "repeating",
+    "position",
+    "volume",
+    "artist",
+    "id",
+    "name",
+    "album",
+    "album_artist",
+    "artwork_url",
+    "disk_number",
+    "duration",
+    "url",
+    "track_number",
+    "explicit",
+    "popularity",
+    "played_count",
+    "starred",
+];
+
+const ACTIONS: &[&str] = &[
+    "play_pause",
+    "play",
+    "pause",
+    "next",
+    "prev",
+    "set_shuffling",
+    "set_repeat_mode",
+    "set_repeating",
+    "set_position",
+    "set_volume",
+    "raise",
+    "quit",
+    "play_uri",
+];
+
+// Runs a shell command for every query and action. Queries come either from
+// one `status` command printing `key=value` lines or a flat JSON object, or
+// from one command per field. A failing `status` or `state` command means the
+// player is not running. `{}` in action commands is replaced by the value.
+pub struct CommandBackend {
+    commands: HashMap<String, String>,
+    cache: Mutex<Option<StatusSnapshot>>,
+}
+
+impl CommandBackend {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<CommandBackend> {
+        let mut commands = HashMap::new();
+
+        for (n, line) in std::fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let err =
+                |msg: &str| Error::new(ErrorKind::InvalidData, format!("line {}: {}", n + 1, msg));
+
+            let pos = line
+                .find('=')
+                .ok_or_else(|| err("expected key = command"))?;
+            let key = line[..pos].trim();
+            if !QUERIES.contains(&key) && !ACTIONS.contains(&key) {
+                return Err(err(&format!("unknown command `{}`", key)));
+            }
+
+            commands.insert(key.to_string(), line[pos + 1..].trim().to_string());
+        }
+
+        if !commands.contains_key("status") && !commands.contains_key("state") {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "either a `status` or a `state` command is required",
+            ));
+        }
+
+        Ok(CommandBackend {
+            commands,
+            cache: Mutex::new(None),
+        })
+    }
+
+    fn status(&self, command: &str) -> Result<StatusSnapshot> {
+        let mut cache = self.cache.lock().unwrap();
+        if let Some(ref snapshot) = *cache {
+            return Ok(snapshot.clone());
+        }
+
+        let output = run(command).map_err(|_| BridgeError::NotRunning)?;
+        let fields = match parse_json(&output) {
+            Some(fields) => fields,
+            None => output
+                .lines()
+                .filter_map(|line| {
+                    let pos = line.find('=')?;
+                    Some((line[..pos].trim().to_string(), line[pos + 1..].to_string()))
+                })
+                .collect(),
+        };
+
+        let snapshot = StatusSnapshot::from_fields(
+            fields
+                .into_iter()
+                .map(normalize)
+                .filter(|(key, _)| QUERIES.contains(&key.as_str())),
+        )?;
+        *cache = Some(snapshot.clone());
+        Ok(snapshot)
+    }
+
+    fn query<T, F>(&self, key: &str, from_status: F) -> Result<Option<T>>
+    where
+        F: FnOnce(StatusSnapshot) -> Option<T>,
+    {
+        if let Some(command) = self.commands.get(key) {
+            let output = if key == "state" {
+                run(command).map_err(|_| BridgeError::NotRunning)?
+            } else {
+                run(command)?
+            };
+            let output = output.trim();
+
+            if output.is_empty() {
+                return Ok(None);
+            }
+
+            let field = normalize((key.to_string(), output.to_string()));
+            Ok(from_status(StatusSnapshot::from_fields(vec![field])?))
+        } else if let Some(command) = self.commands.get("status") {
+            Ok(from_status(self.status(command)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn action(&self, key: &str, value: Option<String>) -> Result<()> {
+        match self.commands.get(key) {
+            Some(command) => {
+                let command = match value {
+                    Some(value) => command.replace("{}", &value),
+                    None => command.clone(),
+                };
+                run(&command)?;
+                // The player changed, the status has to be read again.
+                self.invalidate();
+                Ok(())
+            }
+            None => Err(BridgeError::Backend(Error::new(
+                ErrorKind::Unsupported,
+                format!("no `{}` command configured", key),
+            ))),
+        }
+    }
+}
+
+fn normalize((key, value): (String, String)) -> (String, String) {
+    match key.as_str() {
+        "status" | "state" => ("status".to_string(), value.trim().to_lowercase()),
+        _ => (key, value),
+    }
+}
+
+fn run(command: &str) -> io::Result<String> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // Both pipes are read while the command runs, a full pipe would block it.
+    let mut stdout = child.stdout.take().unwrap();
+    let reader = std::thread::spawn(move || {
+        let mut output = String::new();
+        stdout.read_to_string(&mut output).map(|_| output)
+    });
+    let mut stderr = child.stderr.take().unwrap();
+    let errors = std::thread::spawn(move || {
+        let mut output = Vec::new();
+        let _ = stderr.read_to_end(&mut output);
+        String::from_utf8_lossy(&output).into_owned()
+    });
+
+    let deadline = Instant::now() + COMMAND_TIMEOUT;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+
+        if Instant::now() > deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(Error::new(
+                ErrorKind::TimedOut,
+                format!("`{}` timed out", command),
+            ));
+        }
+
+        std::thread::sleep(Duration::from_millis(5));
+    };
+
+    let output = reader.join().unwrap()?;
+    let stderr = errors.join().unwrap();
+
+    if status.success() {
+        Ok(output)
+    } else {
+        Err(Error::other(format!(
+            "`{}` failed ({}): {}",
+            command,
+            status,
+            stderr.trim()
+        )))
+    }
+}
+
+// Only flat objects are supported, which is all a status blob needs.
+fn parse_json(input: &str) -> Option<Vec<(String, String)>> {
+    let mut chars = input.trim().chars().peekable();
+    let mut fields = Vec::new();
+
+    fn skip_ws(chars: &mut std::iter::Peekable<std::str::Chars>) {
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+    }
+
+    fn string(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<String> {
+        let mut res = String::new();
+        loop {
+            match chars.next()? {
+                '"' => return Some(res),
+                '\\' => match chars.next()? {
+                    'n' => res.push('\n'),
+                    't' => res.push('\t'),
+                    'r' => res.push('\r'),
+                    'b' => res.push('\u{8}'),
+                    'f' => res.push('\u{c}'),
+                    'u' => {
+                        let code: String = chars.by_ref().take(4).collect();
+                        res.push(std::char::from_u32(u32::from_str_radix(&code, 16).ok()?)?);
+                    }
+                    c => res.push(c),
+                },
+                c => res.push(c),
+            }
+        }
+    }
+
+    if chars.next()? != '{' {
+        return None;
+    }
+
+    loop {
+        skip_ws(&mut chars);
+        match chars.next()? {
+            '}' => break,
+            '"' => {}
+            _ => return None,
+        }
+
+        let key = string(&mut chars)?;
+        skip_ws(&mut chars);
+        if chars.next()? != ':' {
+            return None;
+        }
+        skip_ws(&mut chars);
+
+        let value = if chars.peek() == Some(&'"') {
+            chars.next();
+            Some(string(&mut chars)?)
+        } else {
+            let mut raw = String::new();
+            while chars
+                .peek()
+                .is_some_and(|c| !matches!(c, ',' | '}') && !c.is_whitespace())
+            {
+                raw.push(chars.next()?);
+            }
+
+            match raw.as_str() {
+                "" => return None,
+                "null" => None,
+                _ => Some(raw),
+            }
+        };
+
+        if let Some(value) = value {
+            fields.push((key, value));
+        }
+
+        skip_ws(&mut chars);
+        match chars.next()? {
+            ',' => {}
+            '}' => break,
+            _ => return None,
+        }
+    }
+
+    Some(fields)
+}
+
+impl PlayerBackend for CommandBackend {
+    type Track = TrackSnapshot;
+
+    fn state(&self) -> Result<Option<PlaybackStatus>> {
+        self.query("state", |status| Some(status.playback_status))
+    }
+
+    fn is_shuffling(&self) -> Result<Option<bool>> {
+        self.query("shuffling", |status| status.shuffling)
+    }
+
+    fn set_shuffling(&self, value: bool) -> Result<()> {
+        self.action("set_shuffling", Some(value.to_string()))
+    }
+
+    // `repeating` and `set_repeating` only know on and off, for players without track repeat.
+    fn repeat_mode(&self) -> Result<Option<RepeatMode>> {
+        if self.commands.contains_key("repeating") {
+            self.query("repeating", |status| status.repeat_mode)
+        } else {
+            self.query("repeat", |status| status.repeat_mode)
+        }
+    }
+
+    fn set_repeat_mode(&self, value: RepeatMode) -> Result<()> {
+        match value {
+            RepeatMode::Off | RepeatMode::Context
+                if !self.commands.contains_key("set_repeat_mode")
+                    && self.commands.contains_key("set_repeating") =>
+            {
+                let repeating = value == RepeatMode::Context;
+                self.action("set_repeating", Some(repeating.to_string()))
+            }
+            _ => self.action("set_repeat_mode", Some(value.to_string())),
+        }
+    }
+
+    fn position(&self) -> Result<Option<f64>> {
+        self.query("position", |status| status.position)
+    }
+
+    fn set_position(&self, value: f64) -> Result<()> {
+        self.action("set_position", Some(value.to_string()))
+    }
+
+    fn volume(&self) -> Result<Option<i32>> {
+        self.query("volume", |status| status.volume)
+    }
+
+    fn set_volume(&self, value: i32) -> Result<()> {
+        self.action("set_volume", Some(value.to_string()))
+    }
+
+    fn track(&self) -> Result<Option<TrackSnapshot>> {
+        let track = TrackSnapshot {
+            artist: self.query("artist", |status| status.track.artist)?,
+            id: self.query("id", |status| status.track.id)?,
+            name: self.query("name", |status| status.track.name)?,
+            album: self.query("album", |status| status.track.album)?,
+            album_artist: self.query("album_artist", |status| status.track.album_artist)?,
+            artwork_url: self.query("artwork_url", |status| status.track.artwork_url)?,
+            disk_number: self.query("disk_number", |status| status.track.disk_number)?,
+            duration: self.query("duration", |status| status.track.duration)?,
+            url: self.query("url", |status| status.track.url)?,
+            track_number: self.query("track_number", |status| status.track.track_number)?,
+            explicit: self.query("explicit", |status| status.track.explicit)?,
+            popularity: self.query("popularity", |status| status.track.popularity)?,
+            played_count: self.query("played_count", |status| status.track.played_count)?,
+            starred: self.query("starred", |status| status.track.starred)?,
+            ..Default::default()
+        };
+
+        if track.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(track))
+        }
+    }
+
+    fn play_pause(&self) -> Result<()> {
+        self.action("play_pause", None)
+    }
+
+    fn play(&self) -> Result<()> {
+        self.action("play", None)
+    }
+
+    fn pause(&self) -> Result<()> {
+        self.action("pause", None)
+    }
+
+    fn next(&self) -> Result<()> {
+        self.action("next", None)
+    }
+
+    fn prev(&self) -> Result<()> {
+        self.action("prev", None)
+    }
+
+    fn raise(&self) -> Result<()> {
+        self.action("raise", None)
+    }
+
+    fn quit(&self) -> Result<()> {
+        self.action("quit", None)
+    }
+
+    fn play_uri(&self, uri: &SpotifyUri) -> Result<()> {
+        self.action("play_uri", Some(uri.to_string()))
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        let has = |key: &str| self.commands.contains_key(key);
+
+        Capabilities {
+            play: has("play") || has("play_pause"),
+            pause: has("pause") || has("play_pause"),
+            seek: has("set_position"),
+            go_next: has("next"),
+            go_previous: has("prev"),
+            quit: has("quit"),
+            raise: has("raise"),
+            control: ACTIONS
+                .iter()
+                .any(|key| *key != "raise" && *key != "quit" && has(key)),
+        }
+    }
+
+    fn invalidate(&self) {
+        *self.cache.lock().unwrap() = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn large_error_output_does_not_block() {
+        let started = Instant::now();
+        let err =
+            run("head -c 200000 /dev/zero | tr '\\0' x >&2; echo done >&2; exit 3").unwrap_err();
+        assert!(started.elapsed() < COMMAND_TIMEOUT);
+        assert_ne!(err.kind(), ErrorKind::TimedOut);
+        assert!(err.to_string().ends_with("done"), "{}", err);
+    }
+}
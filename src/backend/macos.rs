@@ -0,0 +1,274 @@
+use super::{unsupported, PlayerBackend, PlayerTrack};
+use crate::capabilities::Capabilities;
+use crate::error::{BridgeError, Result};
+use crate::snapshot::StatusSnapshot;
+use crate::status::{PlaybackStatus, RepeatMode};
+use crate::uri::SpotifyUri;
+use macos_spotify::{Spotify as SpotifyClient, SpotifyTrack, State};
+use std::io::{Error, ErrorKind};
+use std::process::Command;
+
+const BUNDLE_ID: &str = "com.spotify.client";
+
+// Reads every field of a poll in one AppleScript run, as `key=value` lines,
+// instead of one Apple Event per field. `{track}` is replaced by the track
+// fields when the metadata is needed too. Spotify not running fails with
+// -600, like the Apple Events do.
+const STATUS_SCRIPT: &str = r#"on field(key, value)
+    if value is missing value then return ""
+    return key & "=" & value & linefeed
+end field
+
+if application id "{bundle}" is not running then error number -600
+tell application id "{bundle}"
+    set out to my field("status", player state as string) & my field("volume", sound volume)
+    if player state is stopped then return out
+    set out to out & my field("shuffling", shuffling) & my field("repeating", repeating)
+    set out to out & my field("position", player position)
+    tell current track
+        set out to out & my field("id", id) & my field("popularity", popularity)
+        set out to out & my field("played_count", played count) & my field("starred", starred)
+{track}    end tell
+    return out
+end tell"#;
+
+const TRACK_SCRIPT: &str = r#"        set out to out & my field("name", name) & my field("artist", artist)
+        set out to out & my field("album", album) & my field("album_artist", album artist)
+        set out to out & my field("artwork_url", artwork url) & my field("url", spotify url)
+        set out to out & my field("disk_number", disc number) & my field("duration", duration)
+        set out to out & my field("track_number", track number)
+"#;
+
+// Controls go through the Apple Events client, polls through AppleScript.
+pub struct MacosBackend {
+    client: SpotifyClient,
+}
+
+impl MacosBackend {
+    pub fn new() -> MacosBackend {
+        MacosBackend {
+            client: SpotifyClient::new(),
+        }
+    }
+}
+
+// The Apple Events client holds raw pointers and is not marked thread safe,
+// but every event it sends is independent, as the bridge has always relied on.
+unsafe impl Send for MacosBackend {}
+unsafe impl Sync for MacosBackend {}
+
+impl PlayerTrack for SpotifyTrack {
+    fn artist(&self) -> Result<Option<String>> {
+        Ok(SpotifyTrack::artist(self)?)
+    }
+
+    fn id(&self) -> Result<Option<String>> {
+        Ok(SpotifyTrack::id(self)?)
+    }
+
+    fn name(&self) -> Result<Option<String>> {
+        Ok(SpotifyTrack::name(self)?)
+    }
+
+    fn album(&self) -> Result<Option<String>> {
+        Ok(SpotifyTrack::album(self)?)
+    }
+
+    fn album_artist(&self) -> Result<Option<String>> {
+        Ok(SpotifyTrack::album_artist(self)?)
+    }
+
+    fn artwork_url(&self) -> Result<Option<String>> {
+        Ok(SpotifyTrack::artwork_url(self)?)
+    }
+
+    fn disk_number(&self) -> Result<Option<i32>> {
+        Ok(SpotifyTrack::disk_number(self)?)
+    }
+
+    fn duration(&self) -> Result<Option<i32>> {
+        Ok(SpotifyTrack::duration(self)?)
+    }
+
+    fn url(&self) -> Result<Option<String>> {
+        Ok(SpotifyTrack::url(self)?)
+    }
+
+    fn track_number(&self) -> Result<Option<i32>> {
+        Ok(SpotifyTrack::track_number(self)?)
+    }
+
+    fn popularity(&self) -> Result<Option<i32>> {
+        Ok(SpotifyTrack::popularity(self)?)
+    }
+
+    fn played_count(&self) -> Result<Option<i32>> {
+        Ok(SpotifyTrack::played_count(self)?)
+    }
+
+    fn starred(&self) -> Result<Option<bool>> {
+        Ok(SpotifyTrack::starred(self)?)
+    }
+}
+
+impl PlayerBackend for MacosBackend {
+    type Track = SpotifyTrack;
+
+    fn state(&self) -> Result<Option<PlaybackStatus>> {
+        Ok(self.client.state()?.map(|state| match state {
+            State::STOPPED => PlaybackStatus::Stopped,
+            State::PLAYING => PlaybackStatus::Playing,
+            State::PAUSED => PlaybackStatus::Paused,
+        }))
+    }
+
+    fn is_shuffling(&self) -> Result<Option<bool>> {
+        Ok(self.client.is_shuffling()?)
+    }
+
+    fn set_shuffling(&self, value: bool) -> Result<()> {
+        Ok(self.client.set_shuffling(value)?)
+    }
+
+    // Spotify only tells AppleScript whether it repeats at all, not what.
+    fn repeat_mode(&self) -> Result<Option<RepeatMode>> {
+        Ok(self.client.is_repeating()?.map(RepeatMode::from))
+    }
+
+    fn set_repeat_mode(&self, value: RepeatMode) -> Result<()> {
+        match value {
+            RepeatMode::Off => Ok(self.client.set_repeating(false)?),
+            RepeatMode::Context => Ok(self.client.set_repeating(true)?),
+            RepeatMode::Track => Err(unsupported("repeat a single track")),
+        }
+    }
+
+    fn position(&self) -> Result<Option<f64>> {
+        Ok(self.client.pos()?)
+    }
+
+    fn set_position(&self, value: f64) -> Result<()> {
+        Ok(self.client.set_pos(value)?)
+    }
+
+    fn volume(&self) -> Result<Option<i32>> {
+        Ok(self.client.volume()?)
+    }
+
+    fn set_volume(&self, value: i32) -> Result<()> {
+        Ok(self.client.set_volume(value)?)
+    }
+
+    fn track(&self) -> Result<Option<SpotifyTrack>> {
+        Ok(self.client.track()?)
+    }
+
+    fn play_pause(&self) -> Result<()> {
+        Ok(self.client.play_pause()?)
+    }
+
+    fn play(&self) -> Result<()> {
+        Ok(self.client.play()?)
+    }
+
+    fn pause(&self) -> Result<()> {
+        Ok(self.client.pause()?)
+    }
+
+    fn next(&self) -> Result<()> {
+        Ok(self.client.next()?)
+    }
+
+    fn prev(&self) -> Result<()> {
+        Ok(self.client.prev()?)
+    }
+
+    fn raise(&self) -> Result<()> {
+        let app = macos_open::app_for_bundle_id(BUNDLE_ID).ok_or_else(|| {
+            BridgeError::Backend(Error::new(ErrorKind::NotFound, "Spotify is not installed"))
+        })?;
+        macos_open::open(&app)?;
+        Ok(())
+    }
+
+    fn quit(&self) -> Result<()> {
+        tell("quit")
+    }
+
+    fn play_uri(&self, uri: &SpotifyUri) -> Result<()> {
+        Ok(self.client.play_track(uri.to_string(), None)?)
+    }
+
+    fn snapshot(&self) -> Result<StatusSnapshot> {
+        read_status(true)
+    }
+
+    fn playback_snapshot(&self) -> Result<StatusSnapshot> {
+        read_status(false)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            quit: true,
+            raise: true,
+            ..Capabilities::default()
+        }
+    }
+}
+
+fn read_status(metadata: bool) -> Result<StatusSnapshot> {
+    let script = STATUS_SCRIPT
+        .replace("{bundle}", BUNDLE_ID)
+        .replace("{track}", if metadata { TRACK_SCRIPT } else { "" });
+
+    let fields = osascript(&script)?
+        .lines()
+        .filter_map(|line| {
+            let pos = line.find('=')?;
+            let key = &line[..pos];
+            let mut value = line[pos + 1..].to_string();
+            // Numbers are written with the decimal separator of the locale.
+            if key == "position" {
+                value = value.replace(',', ".");
+            }
+            // Spotify gives the `spotify:` URI, clients have always been sent the link.
+            if key == "url" {
+                if let Some(uri) = SpotifyUri::parse(&value) {
+                    value = uri.url();
+                }
+            }
+            Some((key.to_string(), value))
+        })
+        .collect::<Vec<_>>();
+
+    Ok(StatusSnapshot::from_fields(fields)?)
+}
+
+// Runs what the client has no event for, like quitting, through AppleScript.
+fn tell(command: &str) -> Result<()> {
+    osascript(&format!(
+        "tell application id \"{}\" to {}",
+        BUNDLE_ID, command
+    ))
+    .map(|_| ())
+}
+
+fn osascript(script: &str) -> Result<String> {
+    let output = Command::new("osascript").arg("-e").arg(script).output()?;
+
+    if output.status.success() {
+        return Ok(String::from_utf8_lossy(&output.stdout).into_owned());
+    }
+
+    // Errors end with the OS error code, like `... (-1743)`.
+    let message = String::from_utf8_lossy(&output.stderr).trim().to_string();
+    let code = message
+        .strip_suffix(')')
+        .and_then(|message| message.rsplit('(').next())
+        .and_then(|code| code.parse().ok());
+
+    match code {
+        Some(code) => Err(Error::from_raw_os_error(code).into()),
+        None => Err(BridgeError::Backend(Error::other(message))),
+    }
+}
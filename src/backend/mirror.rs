@@ -0,0 +1,628 @@
+use super::{PlayerBackend, POLL_INTERVAL};
+use crate::capabilities::Capabilities;
+use crate::config::{bus_name, BusKind};
+use crate::error::{BridgeError, Result};
+use crate::snapshot::{StatusSnapshot, TrackSnapshot};
+use crate::status::{PlaybackStatus, RepeatMode};
+use crate::uri::SpotifyUri;
+use dbus::arg::{ArgType, IterAppend, RefArg, Variant};
+use dbus::stdintf::org_freedesktop_dbus::{Properties, PropertiesPropertiesChanged};
+use dbus::{ConnPath, Connection, SignalArgs};
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread::spawn;
+use std::time::{Duration, Instant};
+
+const PATH: &str = "/org/mpris/MediaPlayer2";
+const ROOT_INTERFACE: &str = "org.mpris.MediaPlayer2";
+const PLAYER_INTERFACE: &str = "org.mpris.MediaPlayer2.Player";
+// The only properties of the root interface that matter to the bridge.
+const ROOT_PROPERTIES: &[&str] = &["CanQuit", "CanRaise"];
+const CALL_TIMEOUT: i32 = 2_000;
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+type Status = Option<(StatusSnapshot, Capabilities)>;
+type Job = Box<dyn FnOnce(&Connection, &str) -> std::result::Result<(), dbus::Error> + Send>;
+
+// The mirrored player's properties live on a private connection owned by a
+// worker thread; control calls are handed to it and wait for its answer.
+pub struct MirrorBackend {
+    status: Arc<Mutex<Status>>,
+    jobs: Mutex<Sender<(Job, Sender<Result<()>>)>>,
+}
+
+impl MirrorBackend {
+    pub fn connect(name: &str, bus: &BusKind) -> Result<MirrorBackend> {
+        let name = bus_name(name);
+        let conn = bus.connect()?;
+        let status = Arc::new(Mutex::new(None));
+        let (tx, rx) = channel();
+
+        {
+            let status = status.clone();
+            // The connection is only ever used from the worker thread.
+            let conn = SendConnection(conn);
+            spawn(move || {
+                let SendConnection(conn) = conn;
+                run_mirror(conn, &name, &status, rx)
+            });
+        }
+
+        Ok(MirrorBackend {
+            status,
+            jobs: Mutex::new(tx),
+        })
+    }
+
+    fn current(&self) -> Result<StatusSnapshot> {
+        match *self.status.lock().unwrap() {
+            Some((ref snapshot, _)) => Ok(snapshot.clone()),
+            None => Err(BridgeError::NotRunning),
+        }
+    }
+
+    fn call(&self, interface: &'static str, method: &'static str) -> Result<()> {
+        self.run(Box::new(move |conn, name| {
+            conn.with_path(name, PATH, CALL_TIMEOUT)
+                .method_call_with_args(&interface.into(), &method.into(), |_| {})
+                .map(|_| ())
+        }))
+    }
+
+    fn set<T: dbus::arg::Arg + dbus::arg::Append + Send + 'static>(
+        &self,
+        property: &'static str,
+        value: T,
+    ) -> Result<()> {
+        self.run(Box::new(move |conn, name| {
+            conn.with_path(name, PATH, CALL_TIMEOUT)
+                .set(PLAYER_INTERFACE, property, value)
+        }))
+    }
+
+    fn run(&self, job: Job) -> Result<()> {
+        let closed = || {
+            BridgeError::Backend(Error::new(
+                ErrorKind::NotConnected,
+                "mirror connection closed",
+            ))
+        };
+
+        let (tx, rx) = channel();
+        self.jobs
+            .lock()
+            .unwrap()
+            .send((job, tx))
+            .map_err(|_| closed())?;
+        rx.recv().map_err(|_| closed())?
+    }
+}
+
+struct SendConnection(Connection);
+
+unsafe impl Send for SendConnection {}
+
+fn run_mirror(
+    conn: Connection,
+    name: &str,
+    status: &Mutex<Status>,
+    jobs: Receiver<(Job, Sender<Result<()>>)>,
+) {
+    // The bus daemon resolves the well-known name, so only the mirrored player's signals arrive.
+    let rule = PropertiesPropertiesChanged::match_str(Some(&name.into()), Some(&PATH.into()));
+    let owner_rule = format!(
+        "type='signal',interface='org.freedesktop.DBus',member='NameOwnerChanged',arg0='{}'",
+        name
+    );
+    if let Err(err) = conn
+        .add_match(&rule)
+        .and_then(|_| conn.add_match(&owner_rule))
+    {
+        println!("Cannot watch {}: {}", name, BridgeError::DBus(err));
+    }
+
+    let mut props: Option<HashMap<String, Variant<Box<dyn RefArg>>>> = None;
+    let mut last_poll = Instant::now() - RETRY_DELAY;
+
+    loop {
+        loop {
+            match jobs.try_recv() {
+                Ok((job, reply)) => {
+                    let _ = reply.send(job(&conn, name).map_err(BridgeError::from));
+                    last_poll = Instant::now() - RETRY_DELAY;
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return,
+            }
+        }
+
+        // Properties the player announced as changed without sending them.
+        let mut invalidated = Vec::new();
+
+        for msg in conn.incoming(POLL_INTERVAL.as_millis() as u32 / 4) {
+            if let Some(mut changed) = PropertiesPropertiesChanged::from_message(&msg) {
+                if changed.interface_name == ROOT_INTERFACE {
+                    changed
+                        .changed_properties
+                        .retain(|key, _| ROOT_PROPERTIES.contains(&key.as_str()));
+                    changed
+                        .invalidated_properties
+                        .retain(|key| ROOT_PROPERTIES.contains(&key.as_str()));
+                } else if changed.interface_name != PLAYER_INTERFACE {
+                    continue;
+                }
+
+                if let Some(ref mut props) = props {
+                    props.extend(changed.changed_properties);
+                    for key in changed.invalidated_properties {
+                        invalidated.push((changed.interface_name.clone(), key));
+                    }
+                }
+            } else if msg.member().as_deref() == Some("NameOwnerChanged") {
+                // Forget everything, the next refresh reloads from the new owner if any.
+                props = None;
+                last_poll = Instant::now() - RETRY_DELAY;
+            }
+        }
+
+        let player = conn.with_path(name, PATH, CALL_TIMEOUT);
+
+        if props.is_none() && last_poll.elapsed() >= RETRY_DELAY {
+            props = player.get_all(PLAYER_INTERFACE).ok();
+            if let Some(ref mut props) = props {
+                for key in ROOT_PROPERTIES {
+                    if let Ok(value) = player.get::<bool>(ROOT_INTERFACE, key) {
+                        props.insert(key.to_string(), Variant(Box::new(value)));
+                    }
+                }
+            }
+            last_poll = Instant::now();
+        } else if let Some(ref mut props) = props {
+            for (interface, key) in invalidated {
+                match get(&player, &interface, &key) {
+                    Some(value) => props.insert(key, value),
+                    None => props.remove(&key),
+                };
+            }
+
+            // Position never comes with PropertiesChanged, so it has to be polled.
+            if last_poll.elapsed() >= POLL_INTERVAL {
+                if let Ok(position) = player.get::<i64>(PLAYER_INTERFACE, "Position") {
+                    props.insert("Position".to_string(), Variant(Box::new(position)));
+                }
+                last_poll = Instant::now();
+            }
+        }
+
+        *status.lock().unwrap() = props
+            .as_ref()
+            .map(|props| (to_snapshot(props), to_capabilities(props)));
+    }
+}
+
+// A property of any type, `Properties::get` needs to know it up front.
+fn get(
+    player: &ConnPath<&Connection>,
+    interface: &str,
+    key: &str,
+) -> Option<Variant<Box<dyn RefArg>>> {
+    player
+        .method_call_with_args(
+            &"org.freedesktop.DBus.Properties".into(),
+            &"Get".into(),
+            |msg| {
+                let mut args = IterAppend::new(msg);
+                args.append(interface);
+                args.append(key);
+            },
+        )
+        .ok()?
+        .read1()
+        .ok()
+}
+
+fn unwrap(arg: &dyn RefArg) -> &dyn RefArg {
+    if arg.arg_type() == ArgType::Variant {
+        match arg.as_iter().and_then(|mut iter| iter.next()) {
+            Some(inner) => unwrap(inner),
+            None => arg,
+        }
+    } else {
+        arg
+    }
+}
+
+fn string(arg: &dyn RefArg) -> Option<String> {
+    let arg = unwrap(arg);
+
+    if arg.arg_type() == ArgType::Array {
+        let items: Vec<String> = arg
+            .as_iter()?
+            .filter_map(|item| unwrap(item).as_str().map(String::from))
+            .collect();
+        if items.is_empty() {
+            None
+        } else {
+            Some(items.join(", "))
+        }
+    } else {
+        arg.as_str().map(String::from)
+    }
+}
+
+// The items of a string array, a plain string is left to the artist rules.
+fn strings(arg: &dyn RefArg) -> Vec<String> {
+    let arg = unwrap(arg);
+
+    match arg.as_iter() {
+        Some(items) if arg.arg_type() == ArgType::Array => items
+            .filter_map(|item| unwrap(item).as_str().map(String::from))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn integer(arg: &dyn RefArg) -> Option<i64> {
+    let arg = unwrap(arg);
+    arg.as_i64()
+        .or_else(|| arg.as_u64().map(|v| v as i64))
+        .or_else(|| arg.as_f64().map(|v| v as i64))
+}
+
+fn to_track(metadata: &dyn RefArg) -> TrackSnapshot {
+    let mut track = TrackSnapshot::default();
+    let mut iter = match unwrap(metadata).as_iter() {
+        Some(iter) => iter,
+        None => return track,
+    };
+
+    while let (Some(key), Some(value)) = (iter.next(), iter.next()) {
+        match key.as_str().unwrap_or_default() {
+            "mpris:trackid" => track.id = string(value),
+            "mpris:length" => track.duration = integer(value).map(|v| (v / 1_000) as i32),
+            "mpris:artUrl" => track.artwork_url = string(value),
+            "xesam:title" => track.name = string(value),
+            "xesam:album" => track.album = string(value),
+            "xesam:artist" => {
+                track.artist = string(value);
+                track.artists = strings(value);
+            }
+            "xesam:albumArtist" => {
+                track.album_artist = string(value);
+                track.album_artists = strings(value);
+            }
+            "xesam:discNumber" => track.disk_number = integer(value).map(|v| v as i32),
+            "xesam:url" => track.url = string(value),
+            "xesam:trackNumber" => track.track_number = integer(value).map(|v| v as i32),
+            "xesam:useCount" => track.played_count = integer(value).map(|v| v as i32),
+            "xesam:autoRating" => {
+                track.popularity = unwrap(value).as_f64().map(|v| (v * 100.0).round() as i32)
+            }
+            "spotifybridge:explicit" => track.explicit = integer(value).map(|v| v != 0),
+            "spotifybridge:starred" => track.starred = integer(value).map(|v| v != 0),
+            _ => {}
+        }
+    }
+
+    track
+}
+
+fn to_snapshot(props: &HashMap<String, Variant<Box<dyn RefArg>>>) -> StatusSnapshot {
+    let get = |key: &str| props.get(key).map(|v| unwrap(&*v.0));
+
+    StatusSnapshot {
+        playback_status: match get("PlaybackStatus").and_then(|v| v.as_str()) {
+            Some("Playing") => PlaybackStatus::Playing,
+            Some("Paused") => PlaybackStatus::Paused,
+            _ => PlaybackStatus::Stopped,
+        },
+        shuffling: get("Shuffle").and_then(|v| v.as_i64()).map(|v| v != 0),
+        repeat_mode: match get("LoopStatus").and_then(|v| v.as_str()) {
+            Some("None") => Some(RepeatMode::Off),
+            Some("Playlist") => Some(RepeatMode::Context),
+            Some("Track") => Some(RepeatMode::Track),
+            _ => None,
+        },
+        position: get("Position")
+            .and_then(|v| v.as_i64())
+            .map(|v| v as f64 / 1_000_000.0),
+        volume: get("Volume")
+            .and_then(|v| v.as_f64())
+            .map(|v| (v * 100.0).round() as i32),
+        track: get("Metadata").map(to_track).unwrap_or_default(),
+    }
+}
+
+fn to_capabilities(props: &HashMap<String, Variant<Box<dyn RefArg>>>) -> Capabilities {
+    let get = |key: &str| {
+        props
+            .get(key)
+            .and_then(|v| unwrap(&*v.0).as_i64())
+            .is_some_and(|v| v != 0)
+    };
+
+    Capabilities {
+        play: get("CanPlay"),
+        pause: get("CanPause"),
+        seek: get("CanSeek"),
+        go_next: get("CanGoNext"),
+        go_previous: get("CanGoPrevious"),
+        control: get("CanControl"),
+        quit: get("CanQuit"),
+        raise: get("CanRaise"),
+    }
+}
+
+impl PlayerBackend for MirrorBackend {
+    type Track = TrackSnapshot;
+
+    fn state(&self) -> Result<Option<PlaybackStatus>> {
+        Ok(Some(self.current()?.playback_status))
+    }
+
+    fn is_shuffling(&self) -> Result<Option<bool>> {
+        Ok(self.current()?.shuffling)
+    }
+
+    fn set_shuffling(&self, value: bool) -> Result<()> {
+        self.set("Shuffle", value)
+    }
+
+    fn repeat_mode(&self) -> Result<Option<RepeatMode>> {
+        Ok(self.current()?.repeat_mode)
+    }
+
+    fn set_repeat_mode(&self, value: RepeatMode) -> Result<()> {
+        let value = match value {
+            RepeatMode::Off => "None",
+            RepeatMode::Context => "Playlist",
+            RepeatMode::Track => "Track",
+        };
+        self.set("LoopStatus", value)
+    }
+
+    fn position(&self) -> Result<Option<f64>> {
+        Ok(self.current()?.position)
+    }
+
+    fn set_position(&self, value: f64) -> Result<()> {
+        let track_id = self.current()?.track.id;
+        let offset = (value * 1_000_000.0).round() as i64;
+
+        self.run(Box::new(move |conn, name| {
+            let track_id = track_id
+                .and_then(|id| dbus::Path::new(id).ok())
+                .unwrap_or_else(|| "/org/mpris/MediaPlayer2/TrackList/NoTrack".into());
+
+            conn.with_path(name, PATH, CALL_TIMEOUT)
+                .method_call_with_args(&PLAYER_INTERFACE.into(), &"SetPosition".into(), |msg| {
+                    let mut args = dbus::arg::IterAppend::new(msg);
+                    args.append(track_id);
+                    args.append(offset);
+                })
+                .map(|_| ())
+        }))
+    }
+
+    fn volume(&self) -> Result<Option<i32>> {
+        Ok(self.current()?.volume)
+    }
+
+    fn set_volume(&self, value: i32) -> Result<()> {
+        self.set("Volume", f64::from(value) / 100.0)
+    }
+
+    fn track(&self) -> Result<Option<TrackSnapshot>> {
+        let track = self.current()?.track;
+
+        if track.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(track))
+        }
+    }
+
+    fn play_pause(&self) -> Result<()> {
+        self.call(PLAYER_INTERFACE, "PlayPause")
+    }
+
+    fn play(&self) -> Result<()> {
+        self.call(PLAYER_INTERFACE, "Play")
+    }
+
+    fn pause(&self) -> Result<()> {
+        self.call(PLAYER_INTERFACE, "Pause")
+    }
+
+    fn next(&self) -> Result<()> {
+        self.call(PLAYER_INTERFACE, "Next")
+    }
+
+    fn prev(&self) -> Result<()> {
+        self.call(PLAYER_INTERFACE, "Previous")
+    }
+
+    fn raise(&self) -> Result<()> {
+        self.call(ROOT_INTERFACE, "Raise")
+    }
+
+    fn quit(&self) -> Result<()> {
+        self.call(ROOT_INTERFACE, "Quit")
+    }
+
+    fn play_uri(&self, uri: &SpotifyUri) -> Result<()> {
+        let uri = uri.to_string();
+
+        self.run(Box::new(move |conn, name| {
+            conn.with_path(name, PATH, CALL_TIMEOUT)
+                .method_call_with_args(&PLAYER_INTERFACE.into(), &"OpenUri".into(), |msg| {
+                    dbus::arg::IterAppend::new(msg).append(uri);
+                })
+                .map(|_| ())
+        }))
+    }
+
+    fn snapshot(&self) -> Result<StatusSnapshot> {
+        self.current()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        match *self.status.lock().unwrap() {
+            Some((_, capabilities)) => capabilities,
+            None => Capabilities::none(),
+        }
+    }
+
+    fn playback_snapshot(&self) -> Result<StatusSnapshot> {
+        self.snapshot()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mpris::tests::{bus_address, Bridge};
+    use dbus::tree::Factory;
+    use std::thread::sleep;
+
+    fn metadata(
+        entries: Vec<(&str, Box<dyn RefArg>)>,
+    ) -> HashMap<String, Variant<Box<dyn RefArg>>> {
+        entries
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), Variant(value)))
+            .collect()
+    }
+
+    #[test]
+    fn artist_lists_are_kept() {
+        let track = to_track(&metadata(vec![
+            (
+                "xesam:artist",
+                Box::new(vec![
+                    "Crosby, Stills & Nash".to_string(),
+                    "Young".to_string(),
+                ]),
+            ),
+            ("xesam:albumArtist", Box::new(vec!["Various".to_string()])),
+        ]));
+
+        assert_eq!(
+            track.artist.as_deref(),
+            Some("Crosby, Stills & Nash, Young")
+        );
+        assert_eq!(track.artists, vec!["Crosby, Stills & Nash", "Young"]);
+        assert_eq!(track.album_artist.as_deref(), Some("Various"));
+        assert_eq!(track.album_artists, vec!["Various"]);
+    }
+
+    #[test]
+    fn artist_strings_are_left_to_the_rules() {
+        let track = to_track(&metadata(vec![(
+            "xesam:artist",
+            Box::new("Simon & Garfunkel".to_string()),
+        )]));
+
+        assert_eq!(track.artist.as_deref(), Some("Simon & Garfunkel"));
+        assert!(track.artists.is_empty());
+    }
+
+    fn wait_for<F: Fn() -> bool>(done: F) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !done() {
+            assert!(Instant::now() < deadline, "timed out");
+            sleep(Duration::from_millis(250));
+        }
+    }
+
+    fn name_of(mirror: &MirrorBackend) -> Option<String> {
+        mirror
+            .snapshot()
+            .ok()
+            .and_then(|snapshot| snapshot.track.name)
+    }
+
+    #[test]
+    fn mirrors_a_player() {
+        let bridge = Bridge::start();
+        let mirror =
+            MirrorBackend::connect(&bridge.name, &BusKind::Address(bus_address())).unwrap();
+
+        wait_for(|| name_of(&mirror).as_deref() == Some("Never Gonna Give You Up"));
+        assert!(mirror.capabilities().raise);
+        assert_eq!(mirror.state().unwrap(), Some(PlaybackStatus::Paused));
+
+        mirror.next().unwrap();
+        mirror.set_volume(30).unwrap();
+        wait_for(|| name_of(&mirror).as_deref() == Some("Take On Me"));
+        wait_for(|| mirror.volume().unwrap() == Some(30));
+        assert_eq!(bridge.state.backend().volume().unwrap(), Some(30));
+    }
+
+    // A player that only says its metadata changed, like some browsers do.
+    fn invalidating_player(
+        name: &'static str,
+        title: Arc<Mutex<String>>,
+        invalidate: Receiver<()>,
+    ) {
+        spawn(move || {
+            let conn = BusKind::Address(bus_address()).connect().unwrap();
+            conn.register_name(name, 0).unwrap();
+
+            let f = Factory::new_fn::<()>();
+            let status = f
+                .property::<&str, _>("PlaybackStatus", ())
+                .on_get(|iter, _| {
+                    iter.append("Playing");
+                    Ok(())
+                });
+            let metadata = f
+                .property::<HashMap<String, Variant<Box<dyn RefArg>>>, _>("Metadata", ())
+                .on_get(move |iter, _| {
+                    let mut metadata: HashMap<String, Variant<Box<dyn RefArg>>> = HashMap::new();
+                    let title = title.lock().unwrap().clone();
+                    metadata.insert("xesam:title".to_string(), Variant(Box::new(title)));
+                    iter.append(metadata);
+                    Ok(())
+                });
+            let tree = f.tree(()).add(
+                f.object_path(PATH, ()).introspectable().add(
+                    f.interface(PLAYER_INTERFACE, ())
+                        .add_p(status)
+                        .add_p(metadata),
+                ),
+            );
+            tree.set_registered(&conn, true).unwrap();
+            conn.add_handler(tree);
+
+            loop {
+                conn.incoming(50).next();
+                if invalidate.try_recv().is_ok() {
+                    let changed = PropertiesPropertiesChanged {
+                        interface_name: PLAYER_INTERFACE.to_string(),
+                        invalidated_properties: vec!["Metadata".to_string()],
+                        ..Default::default()
+                    };
+                    conn.send(changed.to_emit_message(&PATH.into())).unwrap();
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn invalidated_properties_are_read_again() {
+        let name = "org.mpris.MediaPlayer2.invalidating";
+        let title = Arc::new(Mutex::new("First".to_string()));
+        let (tx, rx) = channel();
+        invalidating_player(name, title.clone(), rx);
+
+        let mirror = MirrorBackend::connect(name, &BusKind::Address(bus_address())).unwrap();
+        wait_for(|| name_of(&mirror).as_deref() == Some("First"));
+
+        *title.lock().unwrap() = "Second".to_string();
+        tx.send(()).unwrap();
+        wait_for(|| name_of(&mirror).as_deref() == Some("Second"));
+    }
+}
@@ -0,0 +1,551 @@
+use super::{PlayerBackend, PlayerTrack};
+use crate::capabilities::Capabilities;
+use crate::error::{BridgeError, Result};
+use crate::snapshot::{StatusSnapshot, TrackSnapshot};
+use crate::status::{PlaybackStatus, RepeatMode};
+use crate::uri::SpotifyUri;
+use std::io::{Error, ErrorKind};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+fn track(id: &str, artist: &str, name: &str, album: &str, duration: i32) -> TrackSnapshot {
+    TrackSnapshot {
+        artist: Some(artist.to_string()),
+        id: Some(id.to_string()),
+        name: Some(name.to_string()),
+        album: Some(album.to_string()),
+        album_artist: Some(artist.to_string()),
+        artwork_url: None,
+        disk_number: Some(1),
+        duration: Some(duration),
+        url: Some(id.to_string()),
+        ..Default::default()
+    }
+}
+
+fn invalid(msg: String) -> BridgeError {
+    BridgeError::Backend(Error::new(ErrorKind::InvalidInput, msg))
+}
+
+fn parse_value<T: std::str::FromStr>(key: &str, value: Option<String>) -> Result<Option<T>> {
+    match value {
+        Some(value) => match value.parse() {
+            Ok(value) => Ok(Some(value)),
+            Err(_) => Err(invalid(format!("invalid value `{}` for `{}`", value, key))),
+        },
+        None => Ok(None),
+    }
+}
+
+// What calls fail with after `fail`, the errors a real player gives.
+fn failure(kind: ErrorKind) -> BridgeError {
+    match kind {
+        ErrorKind::TimedOut => BridgeError::Timeout,
+        ErrorKind::PermissionDenied => BridgeError::PermissionDenied,
+        kind => BridgeError::Backend(Error::new(kind, "the mock was told to fail")),
+    }
+}
+
+fn parse_switch(value: Option<&str>) -> Result<bool> {
+    match value {
+        Some("on") | Some("true") | Some("1") => Ok(true),
+        Some("off") | Some("false") | Some("0") => Ok(false),
+        _ => Err(invalid("expected on or off".to_string())),
+    }
+}
+
+// Every call into the mock, including track getters, counts as a round trip
+// to the player and pays the configured latency.
+#[derive(Default)]
+struct RoundTrips {
+    latency: Mutex<Duration>,
+    count: AtomicU64,
+}
+
+impl RoundTrips {
+    fn pay(&self) {
+        self.count.fetch_add(1, Ordering::Relaxed);
+        std::thread::sleep(*self.latency.lock().unwrap());
+    }
+}
+
+pub struct MockTrack {
+    track: TrackSnapshot,
+    trips: Arc<RoundTrips>,
+}
+
+impl MockTrack {
+    fn get<T: Clone>(&self, value: &Option<T>) -> Result<Option<T>> {
+        self.trips.pay();
+        Ok(value.clone())
+    }
+}
+
+impl PlayerTrack for MockTrack {
+    fn artist(&self) -> Result<Option<String>> {
+        self.get(&self.track.artist)
+    }
+
+    fn id(&self) -> Result<Option<String>> {
+        self.get(&self.track.id)
+    }
+
+    fn name(&self) -> Result<Option<String>> {
+        self.get(&self.track.name)
+    }
+
+    fn album(&self) -> Result<Option<String>> {
+        self.get(&self.track.album)
+    }
+
+    fn album_artist(&self) -> Result<Option<String>> {
+        self.get(&self.track.album_artist)
+    }
+
+    fn artwork_url(&self) -> Result<Option<String>> {
+        self.get(&self.track.artwork_url)
+    }
+
+    fn disk_number(&self) -> Result<Option<i32>> {
+        self.get(&self.track.disk_number)
+    }
+
+    fn duration(&self) -> Result<Option<i32>> {
+        self.get(&self.track.duration)
+    }
+
+    fn url(&self) -> Result<Option<String>> {
+        self.get(&self.track.url)
+    }
+
+    fn track_number(&self) -> Result<Option<i32>> {
+        self.get(&self.track.track_number)
+    }
+
+    fn explicit(&self) -> Result<Option<bool>> {
+        self.get(&self.track.explicit)
+    }
+
+    fn popularity(&self) -> Result<Option<i32>> {
+        self.get(&self.track.popularity)
+    }
+
+    fn played_count(&self) -> Result<Option<i32>> {
+        self.get(&self.track.played_count)
+    }
+
+    fn starred(&self) -> Result<Option<bool>> {
+        self.get(&self.track.starred)
+    }
+}
+
+struct MockState {
+    running: bool,
+    status: PlaybackStatus,
+    tracks: Vec<TrackSnapshot>,
+    current: usize,
+    position: f64,
+    since: Instant,
+    volume: i32,
+    shuffling: bool,
+    repeat_mode: RepeatMode,
+    seed: u32,
+    failing: Option<ErrorKind>,
+}
+
+impl MockState {
+    fn playback_status(&self) -> PlaybackStatus {
+        if self.tracks.is_empty() {
+            PlaybackStatus::Stopped
+        } else {
+            self.status
+        }
+    }
+
+    fn position(&self) -> f64 {
+        if self.status == PlaybackStatus::Playing {
+            self.position + self.since.elapsed().as_secs_f64()
+        } else {
+            self.position
+        }
+    }
+
+    fn seek(&mut self, position: f64) {
+        self.position = position.max(0.0);
+        self.since = Instant::now();
+    }
+
+    fn set_status(&mut self, status: PlaybackStatus) {
+        let position = self.position();
+        self.status = status;
+        self.seek(position);
+    }
+
+    fn current(&self) -> Option<&TrackSnapshot> {
+        self.tracks.get(self.current)
+    }
+
+    fn random(&mut self) -> usize {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 17;
+        self.seed ^= self.seed << 5;
+        self.seed as usize
+    }
+
+    fn skip(&mut self) {
+        let len = self.tracks.len();
+
+        if len == 0 {
+            return;
+        }
+
+        if self.shuffling && len > 1 {
+            let offset = 1 + self.random() % (len - 1);
+            self.current = (self.current + offset) % len;
+        } else if self.current + 1 < len {
+            self.current += 1;
+        } else {
+            self.current = 0;
+
+            if self.repeat_mode == RepeatMode::Off {
+                self.status = PlaybackStatus::Paused;
+            }
+        }
+
+        self.seek(0.0);
+    }
+
+    fn back(&mut self) {
+        if self.position() <= 3.0 && !self.tracks.is_empty() {
+            self.current = if self.current == 0 {
+                self.tracks.len() - 1
+            } else {
+                self.current - 1
+            };
+        }
+
+        self.seek(0.0);
+    }
+
+    // The mock cannot look anything up, so unknown URIs play as a bare track.
+    fn play_uri(&mut self, uri: &str) {
+        let index = self
+            .tracks
+            .iter()
+            .position(|track| track.id.as_deref() == Some(uri));
+
+        self.current = match index {
+            Some(index) => index,
+            None => {
+                self.tracks.push(TrackSnapshot {
+                    id: Some(uri.to_string()),
+                    ..Default::default()
+                });
+                self.tracks.len() - 1
+            }
+        };
+        self.status = PlaybackStatus::Playing;
+        self.seek(0.0);
+    }
+
+    fn tick(&mut self) {
+        while self.status == PlaybackStatus::Playing {
+            let duration = match self
+                .current()
+                .and_then(|track| track.duration)
+                .map(|v| f64::from(v) / 1_000.0)
+            {
+                Some(duration) => duration,
+                None => break,
+            };
+
+            let position = self.position();
+            if position < duration {
+                break;
+            }
+
+            if self.repeat_mode == RepeatMode::Track {
+                self.seek(0.0);
+            } else {
+                self.skip();
+            }
+            if self.status == PlaybackStatus::Playing {
+                // Carry the time spent past the end of the track over to the next one.
+                self.seek(position - duration);
+            }
+        }
+    }
+}
+
+pub struct MockBackend {
+    state: Mutex<MockState>,
+    trips: Arc<RoundTrips>,
+}
+
+impl MockBackend {
+    pub fn new() -> MockBackend {
+        MockBackend::with_tracks(vec![
+            track(
+                "spotify:track:4uLU6hMCjMI75M1A2tKUQC",
+                "Rick Astley",
+                "Never Gonna Give You Up",
+                "Whenever You Need Somebody",
+                213_573,
+            ),
+            track(
+                "spotify:track:7GhIk7Il098yCjg4BQjzvb",
+                "a-ha",
+                "Take On Me",
+                "Hunting High and Low",
+                225_280,
+            ),
+            track(
+                "spotify:track:5ghIJDpPoe3CfHMGu71E6T",
+                "Nirvana",
+                "Smells Like Teen Spirit",
+                "Nevermind",
+                301_920,
+            ),
+        ])
+    }
+
+    pub fn with_tracks(tracks: Vec<TrackSnapshot>) -> MockBackend {
+        MockBackend {
+            state: Mutex::new(MockState {
+                running: true,
+                status: PlaybackStatus::Paused,
+                tracks,
+                current: 0,
+                position: 0.0,
+                since: Instant::now(),
+                volume: 50,
+                shuffling: false,
+                repeat_mode: RepeatMode::Off,
+                seed: 0x2545_f491,
+                failing: None,
+            }),
+            trips: Default::default(),
+        }
+    }
+
+    fn with_state<T, F: FnOnce(&mut MockState) -> T>(&self, f: F) -> Result<T> {
+        self.trips.pay();
+        let mut state = self.state.lock().unwrap();
+        if !state.running {
+            return Err(BridgeError::NotRunning);
+        }
+        if let Some(kind) = state.failing {
+            return Err(failure(kind));
+        }
+
+        state.tick();
+        Ok(f(&mut state))
+    }
+
+    pub fn launch(&self) {
+        let mut state = self.state.lock().unwrap();
+        if !state.running {
+            state.running = true;
+            state.status = PlaybackStatus::Paused;
+            state.current = 0;
+            state.seek(0.0);
+        }
+    }
+
+    pub fn set_latency(&self, latency: Duration) {
+        *self.trips.latency.lock().unwrap() = latency;
+    }
+
+    pub fn round_trips(&self) -> u64 {
+        self.trips.count.load(Ordering::Relaxed)
+    }
+
+    pub fn push_track(&self, track: TrackSnapshot) {
+        self.state.lock().unwrap().tracks.push(track);
+    }
+
+    pub fn clear_tracks(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.tracks.clear();
+        state.current = 0;
+        state.status = PlaybackStatus::Stopped;
+        state.seek(0.0);
+    }
+
+    pub fn execute(&self, line: &str) -> Result<()> {
+        let line = line.trim();
+        let mut parts = line.splitn(2, char::is_whitespace);
+        let command = parts.next().unwrap_or_default();
+        let arg = parts.next().map(str::trim);
+
+        match command {
+            "" => Ok(()),
+            "launch" => {
+                self.launch();
+                Ok(())
+            }
+            "quit" => self.quit(),
+            "track" => {
+                self.push_track(TrackSnapshot::decode(arg.unwrap_or_default())?);
+                Ok(())
+            }
+            "clear" => {
+                self.clear_tracks();
+                Ok(())
+            }
+            "play" => self.play(),
+            "pause" => self.pause(),
+            "play_pause" => self.play_pause(),
+            "next" => self.next(),
+            "prev" => self.prev(),
+            "open" => self.play_uri(&arg.unwrap_or_default().parse().map_err(invalid)?),
+            "seek" => self.set_position(parse_value("seek", arg.map(String::from))?.unwrap_or(0.0)),
+            "volume" => self.set_volume(parse_value("volume", arg.map(String::from))?.unwrap_or(0)),
+            "fail" => {
+                let failing = match arg {
+                    Some("timeout") => Some(ErrorKind::TimedOut),
+                    Some("unsupported") => Some(ErrorKind::Unsupported),
+                    Some("permission") => Some(ErrorKind::PermissionDenied),
+                    Some("off") => None,
+                    _ => {
+                        return Err(invalid(
+                            "expected timeout, unsupported, permission or off".to_string(),
+                        ))
+                    }
+                };
+                self.state.lock().unwrap().failing = failing;
+                Ok(())
+            }
+            "shuffle" => self.set_shuffling(parse_switch(arg)?),
+            "repeat" => match arg {
+                Some("track") => self.set_repeat_mode(RepeatMode::Track),
+                arg => self.set_repeat_mode(RepeatMode::from(parse_switch(arg)?)),
+            },
+            _ => Err(invalid(format!("unknown mock command `{}`", command))),
+        }
+    }
+}
+
+impl PlayerBackend for MockBackend {
+    type Track = MockTrack;
+
+    fn state(&self) -> Result<Option<PlaybackStatus>> {
+        self.with_state(|state| Some(state.playback_status()))
+    }
+
+    fn is_shuffling(&self) -> Result<Option<bool>> {
+        self.with_state(|state| Some(state.shuffling))
+    }
+
+    fn set_shuffling(&self, value: bool) -> Result<()> {
+        self.with_state(|state| state.shuffling = value)
+    }
+
+    fn repeat_mode(&self) -> Result<Option<RepeatMode>> {
+        self.with_state(|state| Some(state.repeat_mode))
+    }
+
+    fn set_repeat_mode(&self, value: RepeatMode) -> Result<()> {
+        self.with_state(|state| state.repeat_mode = value)
+    }
+
+    fn position(&self) -> Result<Option<f64>> {
+        self.with_state(|state| Some(state.position()))
+    }
+
+    fn set_position(&self, value: f64) -> Result<()> {
+        self.with_state(|state| state.seek(value))
+    }
+
+    fn volume(&self) -> Result<Option<i32>> {
+        self.with_state(|state| Some(state.volume))
+    }
+
+    fn set_volume(&self, value: i32) -> Result<()> {
+        self.with_state(|state| state.volume = value.clamp(0, 100))
+    }
+
+    fn track(&self) -> Result<Option<MockTrack>> {
+        self.with_state(|state| {
+            state.current().map(|track| MockTrack {
+                track: track.clone(),
+                trips: self.trips.clone(),
+            })
+        })
+    }
+
+    fn play_pause(&self) -> Result<()> {
+        self.with_state(|state| {
+            if state.status == PlaybackStatus::Playing {
+                state.set_status(PlaybackStatus::Paused);
+            } else if !state.tracks.is_empty() {
+                state.set_status(PlaybackStatus::Playing);
+            }
+        })
+    }
+
+    fn play(&self) -> Result<()> {
+        self.with_state(|state| {
+            if !state.tracks.is_empty() {
+                state.set_status(PlaybackStatus::Playing);
+            }
+        })
+    }
+
+    fn pause(&self) -> Result<()> {
+        self.with_state(|state| {
+            if state.status == PlaybackStatus::Playing {
+                state.set_status(PlaybackStatus::Paused);
+            }
+        })
+    }
+
+    fn next(&self) -> Result<()> {
+        self.with_state(MockState::skip)
+    }
+
+    fn prev(&self) -> Result<()> {
+        self.with_state(MockState::back)
+    }
+
+    // Like opening the app, raising a mock that is not running launches it.
+    fn raise(&self) -> Result<()> {
+        self.trips.pay();
+        self.launch();
+        Ok(())
+    }
+
+    fn quit(&self) -> Result<()> {
+        self.trips.pay();
+        self.state.lock().unwrap().running = false;
+        Ok(())
+    }
+
+    fn play_uri(&self, uri: &SpotifyUri) -> Result<()> {
+        self.with_state(|state| state.play_uri(&uri.to_string()))
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            quit: true,
+            raise: true,
+            ..Capabilities::default()
+        }
+    }
+
+    fn snapshot(&self) -> Result<StatusSnapshot> {
+        self.with_state(|state| StatusSnapshot {
+            playback_status: state.playback_status(),
+            shuffling: Some(state.shuffling),
+            repeat_mode: Some(state.repeat_mode),
+            position: Some(state.position()),
+            volume: Some(state.volume),
+            track: state.current().cloned().unwrap_or_default(),
+        })
+    }
+
+    fn playback_snapshot(&self) -> Result<StatusSnapshot> {
+        self.snapshot()
+    }
+}
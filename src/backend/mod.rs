@@ -0,0 +1,187 @@
+mod command;
+#[cfg(target_os = "macos")]
+mod macos;
+mod mirror;
+mod mock;
+mod remote;
+mod replay;
+
+pub use command::CommandBackend;
+#[cfg(target_os = "macos")]
+pub use macos::MacosBackend;
+pub use mirror::MirrorBackend;
+pub use mock::{MockBackend, MockTrack};
+pub use remote::RemoteBackend;
+pub use replay::ReplayBackend;
+
+use crate::capabilities::Capabilities;
+use crate::error::{BridgeError, Result};
+use crate::snapshot::{StatusSnapshot, TrackSnapshot};
+use crate::status::{PlaybackStatus, RepeatMode};
+use crate::uri::SpotifyUri;
+use std::io::{Error, ErrorKind};
+use std::time::Duration;
+
+pub const POLL_INTERVAL: Duration = Duration::from_millis(400);
+
+pub fn unsupported(action: &str) -> BridgeError {
+    BridgeError::Backend(Error::new(
+        ErrorKind::Unsupported,
+        format!("the player cannot {}", action),
+    ))
+}
+
+pub trait PlayerTrack {
+    fn artist(&self) -> Result<Option<String>>;
+
+    fn id(&self) -> Result<Option<String>>;
+
+    fn name(&self) -> Result<Option<String>>;
+
+    fn album(&self) -> Result<Option<String>>;
+
+    fn album_artist(&self) -> Result<Option<String>>;
+
+    fn artwork_url(&self) -> Result<Option<String>>;
+
+    fn disk_number(&self) -> Result<Option<i32>>;
+
+    fn duration(&self) -> Result<Option<i32>>;
+
+    fn url(&self) -> Result<Option<String>>;
+
+    // Not every player knows these, so they default to unknown.
+    fn track_number(&self) -> Result<Option<i32>> {
+        Ok(None)
+    }
+
+    fn explicit(&self) -> Result<Option<bool>> {
+        Ok(None)
+    }
+
+    fn popularity(&self) -> Result<Option<i32>> {
+        Ok(None)
+    }
+
+    fn played_count(&self) -> Result<Option<i32>> {
+        Ok(None)
+    }
+
+    fn starred(&self) -> Result<Option<bool>> {
+        Ok(None)
+    }
+}
+
+pub trait PlayerBackend: Send + Sync + 'static {
+    type Track: PlayerTrack;
+
+    fn state(&self) -> Result<Option<PlaybackStatus>>;
+
+    fn is_shuffling(&self) -> Result<Option<bool>>;
+
+    fn set_shuffling(&self, value: bool) -> Result<()>;
+
+    fn repeat_mode(&self) -> Result<Option<RepeatMode>>;
+
+    fn set_repeat_mode(&self, value: RepeatMode) -> Result<()>;
+
+    fn position(&self) -> Result<Option<f64>>;
+
+    fn set_position(&self, value: f64) -> Result<()>;
+
+    fn volume(&self) -> Result<Option<i32>>;
+
+    fn set_volume(&self, value: i32) -> Result<()>;
+
+    fn track(&self) -> Result<Option<Self::Track>>;
+
+    fn play_pause(&self) -> Result<()>;
+
+    fn play(&self) -> Result<()>;
+
+    fn pause(&self) -> Result<()>;
+
+    fn next(&self) -> Result<()>;
+
+    fn prev(&self) -> Result<()>;
+
+    // Brings the player to the foreground, launching it if needed.
+    fn raise(&self) -> Result<()> {
+        Err(unsupported("raise"))
+    }
+
+    fn quit(&self) -> Result<()> {
+        Err(unsupported("quit"))
+    }
+
+    // Starts playing a track, or an album or playlist from the top.
+    fn play_uri(&self, _uri: &SpotifyUri) -> Result<()> {
+        Err(unsupported("open URIs"))
+    }
+
+    // Everything a poll needs in one query. Players that can only be asked one
+    // field at a time keep the default.
+    fn snapshot(&self) -> Result<StatusSnapshot> {
+        snapshot_by_field(self)
+    }
+
+    // Like `snapshot`, but the track may only carry its id and the fields that
+    // change while it plays, popularity, play count and starring. Polls use it
+    // while the track does not change and take the rest from the cache.
+    fn playback_snapshot(&self) -> Result<StatusSnapshot> {
+        playback_by_field(self)
+    }
+
+    // What the player supports at all, the status narrows it down to what
+    // can be done with the current track.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+
+    // Drops whatever the backend kept from earlier reads, so the next read
+    // asks the player.
+    fn invalidate(&self) {}
+
+    fn wait(&self) {
+        std::thread::sleep(POLL_INTERVAL);
+        self.invalidate();
+    }
+}
+
+pub fn snapshot_by_field<B: PlayerBackend + ?Sized>(backend: &B) -> Result<StatusSnapshot> {
+    by_field(backend, true)
+}
+
+pub fn playback_by_field<B: PlayerBackend + ?Sized>(backend: &B) -> Result<StatusSnapshot> {
+    by_field(backend, false)
+}
+
+fn by_field<B: PlayerBackend + ?Sized>(backend: &B, metadata: bool) -> Result<StatusSnapshot> {
+    let playback_status = backend.state()?.unwrap_or(PlaybackStatus::Stopped);
+
+    if playback_status == PlaybackStatus::Stopped {
+        return Ok(StatusSnapshot {
+            volume: backend.volume()?,
+            ..Default::default()
+        });
+    }
+
+    Ok(StatusSnapshot {
+        playback_status,
+        shuffling: backend.is_shuffling()?,
+        repeat_mode: backend.repeat_mode()?,
+        position: backend.position()?,
+        volume: backend.volume()?,
+        track: match backend.track()? {
+            Some(ref track) if metadata => TrackSnapshot::read(track)?,
+            Some(track) => TrackSnapshot {
+                id: track.id()?,
+                popularity: track.popularity()?,
+                played_count: track.played_count()?,
+                starred: track.starred()?,
+                ..Default::default()
+            },
+            None => TrackSnapshot::default(),
+        },
+    })
+}
@@ -0,0 +1,308 @@
+use super::{PlayerBackend, POLL_INTERVAL};
+use crate::capabilities::Capabilities;
+use crate::error::{BridgeError, Result};
+use crate::remote::Stream;
+use crate::snapshot::{StatusSnapshot, TrackSnapshot};
+use crate::status::{PlaybackStatus, RepeatMode};
+use crate::uri::SpotifyUri;
+use std::io::{BufRead, BufReader, Error, ErrorKind};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{sleep, spawn};
+use std::time::Duration;
+
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+
+struct Shared {
+    // The latest status pushed by the server and how many have been received.
+    status: Mutex<(Option<StatusSnapshot>, u64)>,
+    changed: Condvar,
+    capabilities: Mutex<Capabilities>,
+    writer: Mutex<Option<Stream>>,
+}
+
+pub struct RemoteBackend {
+    shared: Arc<Shared>,
+    replies: Mutex<Receiver<Result<()>>>,
+    seen: Mutex<u64>,
+}
+
+impl RemoteBackend {
+    pub fn connect(addr: &str) -> RemoteBackend {
+        let shared = Arc::new(Shared {
+            status: Mutex::new((None, 0)),
+            changed: Condvar::new(),
+            capabilities: Mutex::new(Capabilities::none()),
+            writer: Mutex::new(None),
+        });
+        let (tx, rx) = channel();
+
+        {
+            let shared = shared.clone();
+            let addr = addr.to_string();
+            spawn(move || loop {
+                match Stream::connect(&addr) {
+                    Ok(stream) => {
+                        println!("Connected to {}", addr);
+                        read_server(&shared, stream, &tx);
+                        println!("Disconnected from {}", addr);
+                    }
+                    Err(err) => println!("Cannot connect to {}: {}", addr, err),
+                }
+
+                sleep(RECONNECT_DELAY);
+            });
+        }
+
+        RemoteBackend {
+            shared,
+            replies: Mutex::new(rx),
+            seen: Mutex::new(0),
+        }
+    }
+
+    // While disconnected the player is reported as stopped, like a player that is not running.
+    fn current(&self) -> StatusSnapshot {
+        self.shared
+            .status
+            .lock()
+            .unwrap()
+            .0
+            .clone()
+            .unwrap_or_default()
+    }
+
+    fn send(&self, command: &str) -> Result<()> {
+        let replies = self.replies.lock().unwrap();
+        // Drop replies to requests that already timed out.
+        while replies.try_recv().is_ok() {}
+
+        let not_connected =
+            || BridgeError::Backend(Error::new(ErrorKind::NotConnected, "not connected"));
+
+        match self.shared.writer.lock().unwrap().as_mut() {
+            Some(stream) => stream.send_line(command)?,
+            None => return Err(not_connected()),
+        }
+
+        match replies.recv_timeout(REPLY_TIMEOUT) {
+            Ok(res) => res,
+            Err(RecvTimeoutError::Timeout) => Err(BridgeError::Timeout),
+            Err(RecvTimeoutError::Disconnected) => Err(not_connected()),
+        }
+    }
+}
+
+fn read_server(shared: &Shared, stream: Stream, replies: &Sender<Result<()>>) {
+    *shared.writer.lock().unwrap() = stream.try_clone().ok();
+
+    for line in BufReader::new(stream).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+
+        if let Some(capabilities) = line.strip_prefix("capabilities ") {
+            *shared.capabilities.lock().unwrap() = Capabilities::decode(capabilities);
+        } else if let Some(status) = line.strip_prefix("status ") {
+            match StatusSnapshot::decode(status) {
+                Ok(snapshot) => {
+                    let mut current = shared.status.lock().unwrap();
+                    *current = (Some(snapshot), current.1 + 1);
+                    shared.changed.notify_all();
+                }
+                Err(err) => println!("Bad status from server: {}", err),
+            }
+        } else if line == "ok" {
+            let _ = replies.send(Ok(()));
+        } else if let Some(err) = line.strip_prefix("error ") {
+            // `error <D-Bus error name> <message>`, older servers only send the message.
+            let err = match err.split_once(' ') {
+                Some((name, message)) if name.contains('.') => {
+                    BridgeError::from_dbus_name(name, message)
+                }
+                _ => BridgeError::Backend(Error::other(err.to_string())),
+            };
+            let _ = replies.send(Err(err));
+        }
+    }
+
+    *shared.writer.lock().unwrap() = None;
+    *shared.capabilities.lock().unwrap() = Capabilities::none();
+    let mut current = shared.status.lock().unwrap();
+    *current = (None, current.1 + 1);
+    shared.changed.notify_all();
+}
+
+impl PlayerBackend for RemoteBackend {
+    type Track = TrackSnapshot;
+
+    fn state(&self) -> Result<Option<PlaybackStatus>> {
+        Ok(Some(self.current().playback_status))
+    }
+
+    fn is_shuffling(&self) -> Result<Option<bool>> {
+        Ok(self.current().shuffling)
+    }
+
+    fn set_shuffling(&self, value: bool) -> Result<()> {
+        self.send(&format!("set_shuffling {}", value))
+    }
+
+    fn repeat_mode(&self) -> Result<Option<RepeatMode>> {
+        Ok(self.current().repeat_mode)
+    }
+
+    fn set_repeat_mode(&self, value: RepeatMode) -> Result<()> {
+        self.send(&format!("set_repeat_mode {}", value))
+    }
+
+    fn position(&self) -> Result<Option<f64>> {
+        Ok(self.current().position)
+    }
+
+    fn set_position(&self, value: f64) -> Result<()> {
+        self.send(&format!("set_position {}", value))
+    }
+
+    fn volume(&self) -> Result<Option<i32>> {
+        Ok(self.current().volume)
+    }
+
+    fn set_volume(&self, value: i32) -> Result<()> {
+        self.send(&format!("set_volume {}", value))
+    }
+
+    fn track(&self) -> Result<Option<TrackSnapshot>> {
+        let track = self.current().track;
+
+        if track.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(track))
+        }
+    }
+
+    fn play_pause(&self) -> Result<()> {
+        self.send("play_pause")
+    }
+
+    fn play(&self) -> Result<()> {
+        self.send("play")
+    }
+
+    fn pause(&self) -> Result<()> {
+        self.send("pause")
+    }
+
+    fn next(&self) -> Result<()> {
+        self.send("next")
+    }
+
+    fn prev(&self) -> Result<()> {
+        self.send("prev")
+    }
+
+    fn snapshot(&self) -> Result<StatusSnapshot> {
+        Ok(self.current())
+    }
+
+    fn playback_snapshot(&self) -> Result<StatusSnapshot> {
+        self.snapshot()
+    }
+
+    fn raise(&self) -> Result<()> {
+        self.send("raise")
+    }
+
+    fn quit(&self) -> Result<()> {
+        self.send("quit")
+    }
+
+    fn play_uri(&self, uri: &SpotifyUri) -> Result<()> {
+        self.send(&format!("play_uri {}", uri))
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        *self.shared.capabilities.lock().unwrap()
+    }
+
+    // Poll as soon as the server pushes a new status, so updates are not delayed twice.
+    fn wait(&self) {
+        let mut seen = self.seen.lock().unwrap();
+        let status = self.shared.status.lock().unwrap();
+        let (status, _) = self
+            .shared
+            .changed
+            .wait_timeout_while(status, POLL_INTERVAL * 5, |status| status.1 == *seen)
+            .unwrap();
+        *seen = status.1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MockBackend;
+    use crate::rules::MetadataRules;
+    use crate::AppState;
+    use std::time::Instant;
+
+    // Waits on the pushed statuses until `done` holds.
+    fn wait_for<F: Fn(&RemoteBackend) -> bool>(client: &RemoteBackend, done: F) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !done(client) {
+            assert!(Instant::now() < deadline, "timed out");
+            client.wait();
+        }
+    }
+
+    #[test]
+    fn controls_a_served_player() {
+        let path = std::env::temp_dir().join(format!("bridge-serve-{}", std::process::id()));
+        let addr = format!("unix:{}", path.display());
+        let server = Arc::new(AppState::new(
+            MockBackend::new(),
+            None,
+            MetadataRules::default(),
+        ));
+
+        {
+            let server = server.clone();
+            let addr = addr.clone();
+            spawn(move || crate::remote::serve(server, &addr));
+        }
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !path.exists() {
+            assert!(Instant::now() < deadline, "not listening");
+            sleep(Duration::from_millis(10));
+        }
+
+        let client = RemoteBackend::connect(&addr);
+        wait_for(&client, |client| {
+            client.snapshot().unwrap().track.name.as_deref() == Some("Never Gonna Give You Up")
+        });
+        assert_eq!(client.capabilities(), server.backend().capabilities());
+        assert_eq!(client.state().unwrap(), Some(PlaybackStatus::Paused));
+
+        client.next().unwrap();
+        client.set_volume(40).unwrap();
+        client.set_repeat_mode(RepeatMode::Track).unwrap();
+        wait_for(&client, |client| {
+            let snapshot = client.snapshot().unwrap();
+            snapshot.track.name.as_deref() == Some("Take On Me")
+                && snapshot.volume == Some(40)
+                && snapshot.repeat_mode == Some(RepeatMode::Track)
+        });
+        assert_eq!(server.backend().volume().unwrap(), Some(40));
+
+        server.backend().execute("quit").unwrap();
+        wait_for(&client, |client| {
+            client.state().unwrap() == Some(PlaybackStatus::Stopped)
+        });
+        assert!(matches!(client.play(), Err(BridgeError::NotRunning)));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
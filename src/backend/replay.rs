@@ -0,0 +1,202 @@
+use super::{PlayerBackend, POLL_INTERVAL};
+use crate::capabilities::Capabilities;
+use crate::error::{BridgeError, Result};
+use crate::record::read_recording;
+use crate::snapshot::{StatusSnapshot, TrackSnapshot};
+use crate::status::{PlaybackStatus, RepeatMode};
+use std::io::{self, Error, ErrorKind};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+pub struct ReplayBackend {
+    frames: Vec<(Duration, StatusSnapshot)>,
+    speed: f64,
+    start: Instant,
+    cursor: Mutex<usize>,
+}
+
+impl ReplayBackend {
+    pub fn open<P: AsRef<Path>>(path: P, speed: f64) -> io::Result<ReplayBackend> {
+        let frames = read_recording(path)?;
+
+        if frames.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidData, "recording is empty"));
+        }
+
+        Ok(ReplayBackend {
+            frames,
+            speed,
+            start: Instant::now(),
+            cursor: Mutex::new(0),
+        })
+    }
+
+    fn frame(&self) -> StatusSnapshot {
+        self.frames[*self.cursor.lock().unwrap()].1.clone()
+    }
+
+    fn read_only(&self) -> Result<()> {
+        Err(BridgeError::Backend(Error::other(
+            "a replayed player cannot be controlled",
+        )))
+    }
+}
+
+impl PlayerBackend for ReplayBackend {
+    type Track = TrackSnapshot;
+
+    fn state(&self) -> Result<Option<PlaybackStatus>> {
+        Ok(Some(self.frame().playback_status))
+    }
+
+    fn is_shuffling(&self) -> Result<Option<bool>> {
+        Ok(self.frame().shuffling)
+    }
+
+    fn set_shuffling(&self, _: bool) -> Result<()> {
+        self.read_only()
+    }
+
+    fn repeat_mode(&self) -> Result<Option<RepeatMode>> {
+        Ok(self.frame().repeat_mode)
+    }
+
+    fn set_repeat_mode(&self, _: RepeatMode) -> Result<()> {
+        self.read_only()
+    }
+
+    fn position(&self) -> Result<Option<f64>> {
+        Ok(self.frame().position)
+    }
+
+    fn set_position(&self, _: f64) -> Result<()> {
+        self.read_only()
+    }
+
+    fn volume(&self) -> Result<Option<i32>> {
+        Ok(self.frame().volume)
+    }
+
+    fn set_volume(&self, _: i32) -> Result<()> {
+        self.read_only()
+    }
+
+    fn track(&self) -> Result<Option<TrackSnapshot>> {
+        let track = self.frame().track;
+
+        if track.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(track))
+        }
+    }
+
+    fn play_pause(&self) -> Result<()> {
+        self.read_only()
+    }
+
+    fn play(&self) -> Result<()> {
+        self.read_only()
+    }
+
+    fn pause(&self) -> Result<()> {
+        self.read_only()
+    }
+
+    fn next(&self) -> Result<()> {
+        self.read_only()
+    }
+
+    fn prev(&self) -> Result<()> {
+        self.read_only()
+    }
+
+    fn snapshot(&self) -> Result<StatusSnapshot> {
+        Ok(self.frame())
+    }
+
+    fn playback_snapshot(&self) -> Result<StatusSnapshot> {
+        self.snapshot()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::none()
+    }
+
+    // Polls happen at the recorded timestamps, so the bridge sees every frame exactly once.
+    fn wait(&self) {
+        let next = *self.cursor.lock().unwrap() + 1;
+
+        match self.frames.get(next) {
+            Some((time, _)) => {
+                // Edited recordings may go back in time, those frames are due at once.
+                let elapsed = time.saturating_sub(self.frames[0].0);
+                let due = self.start + elapsed.div_f64(self.speed);
+                let now = Instant::now();
+                if due > now {
+                    std::thread::sleep(due - now);
+                }
+
+                *self.cursor.lock().unwrap() = next;
+            }
+            None => std::thread::sleep(POLL_INTERVAL),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MockBackend;
+    use crate::record::Recorder;
+    use crate::rules::MetadataRules;
+    use crate::AppState;
+
+    fn path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("bridge-{}-{}", name, std::process::id()))
+    }
+
+    #[test]
+    fn replays_a_recording() {
+        let path = path("replay");
+        let state = AppState::new(
+            MockBackend::new(),
+            Some(Recorder::create(&path).unwrap()),
+            MetadataRules::default(),
+        );
+
+        let mut expected = Vec::new();
+        for command in &["volume 30", "play", "next", "shuffle on", "pause"] {
+            state.backend().execute(command).unwrap();
+            state.update().unwrap();
+            expected.push(state.spotify_status().snapshot());
+        }
+        drop(state);
+
+        let replay = ReplayBackend::open(&path, 1_000.0).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        for (n, snapshot) in expected.iter().enumerate() {
+            if n > 0 {
+                replay.wait();
+            }
+            assert_eq!(replay.snapshot().unwrap(), *snapshot);
+        }
+        assert_eq!(
+            replay.snapshot().unwrap().track.name.as_deref(),
+            Some("Take On Me")
+        );
+    }
+
+    #[test]
+    fn timestamps_may_go_backwards() {
+        let path = path("backwards");
+        std::fs::write(&path, "time=500;status=playing\ntime=100;status=paused\n").unwrap();
+        let replay = ReplayBackend::open(&path, 1.0).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        replay.wait();
+        assert_eq!(replay.state().unwrap(), Some(PlaybackStatus::Paused));
+    }
+}
@@ -0,0 +1,121 @@
+use crate::backend::{snapshot_by_field, MockBackend, MockTrack, PlayerBackend};
+use crate::error::Result;
+use crate::status::{PlaybackStatus, RepeatMode, SpotifyStatus};
+use std::time::Instant;
+
+// Hides the mock's batched queries, so it polls like a player that can only
+// be asked one field at a time.
+struct PerField(MockBackend);
+
+impl PlayerBackend for PerField {
+    type Track = MockTrack;
+
+    fn state(&self) -> Result<Option<PlaybackStatus>> {
+        self.0.state()
+    }
+
+    fn is_shuffling(&self) -> Result<Option<bool>> {
+        self.0.is_shuffling()
+    }
+
+    fn set_shuffling(&self, value: bool) -> Result<()> {
+        self.0.set_shuffling(value)
+    }
+
+    fn repeat_mode(&self) -> Result<Option<RepeatMode>> {
+        self.0.repeat_mode()
+    }
+
+    fn set_repeat_mode(&self, value: RepeatMode) -> Result<()> {
+        self.0.set_repeat_mode(value)
+    }
+
+    fn position(&self) -> Result<Option<f64>> {
+        self.0.position()
+    }
+
+    fn set_position(&self, value: f64) -> Result<()> {
+        self.0.set_position(value)
+    }
+
+    fn volume(&self) -> Result<Option<i32>> {
+        self.0.volume()
+    }
+
+    fn set_volume(&self, value: i32) -> Result<()> {
+        self.0.set_volume(value)
+    }
+
+    fn track(&self) -> Result<Option<MockTrack>> {
+        self.0.track()
+    }
+
+    fn play_pause(&self) -> Result<()> {
+        self.0.play_pause()
+    }
+
+    fn play(&self) -> Result<()> {
+        self.0.play()
+    }
+
+    fn pause(&self) -> Result<()> {
+        self.0.pause()
+    }
+
+    fn next(&self) -> Result<()> {
+        self.0.next()
+    }
+
+    fn prev(&self) -> Result<()> {
+        self.0.prev()
+    }
+}
+
+fn measure<F: Fn() -> Result<()>>(
+    name: &str,
+    backend: &MockBackend,
+    polls: u32,
+    poll: F,
+) -> Result<()> {
+    let trips = backend.round_trips();
+    let start = Instant::now();
+    for _ in 0..polls {
+        poll()?;
+    }
+    let elapsed = start.elapsed().as_secs_f64() * 1_000.0;
+    let trips = backend.round_trips() - trips;
+
+    println!(
+        "{:<10} {:.3} ms and {:.1} round trips per poll",
+        name,
+        elapsed / f64::from(polls),
+        trips as f64 / f64::from(polls)
+    );
+
+    Ok(())
+}
+
+// Compares the cost of a poll when querying every field separately, with a
+// single snapshot query, and with per-field queries behind the metadata cache.
+pub fn run(backend: MockBackend, polls: u32) -> Result<()> {
+    let backend = PerField(backend);
+    let status = SpotifyStatus::default();
+    backend.play()?;
+
+    println!("{} polls, playing", polls);
+    measure("per field", &backend.0, polls, || {
+        snapshot_by_field(&backend).map(|_| ())
+    })?;
+    measure("snapshot", &backend.0, polls, || {
+        backend.0.snapshot().map(|_| ())
+    })?;
+    measure("cached", &backend.0, polls, || status.update(&backend))?;
+
+    let stats = status.metadata_stats();
+    println!(
+        "{:<10} {} metadata fetches, {} cache hits",
+        "", stats.fetches, stats.hits
+    );
+
+    Ok(())
+}
@@ -0,0 +1,42 @@
+use crate::snapshot::TrackSnapshot;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+    pub fetches: u64,
+    pub hits: u64,
+}
+
+// Metadata of the current track, reused for as long as the player reports the
+// same track id. Tracks without an id are always fetched.
+#[derive(Debug, Default)]
+pub struct MetadataCache {
+    track: Option<TrackSnapshot>,
+    stale: bool,
+    stats: CacheStats,
+}
+
+impl MetadataCache {
+    pub fn get(&mut self, id: &Option<String>) -> Option<TrackSnapshot> {
+        match self.track {
+            Some(ref track) if id.is_some() && track.id == *id && !self.stale => {
+                self.stats.hits += 1;
+                Some(track.clone())
+            }
+            _ => None,
+        }
+    }
+
+    pub fn store(&mut self, track: TrackSnapshot) {
+        self.stats.fetches += 1;
+        self.stale = false;
+        self.track = Some(track);
+    }
+
+    pub fn invalidate(&mut self) {
+        self.stale = true;
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+}
@@ -0,0 +1,107 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    pub play: bool,
+    pub pause: bool,
+    pub seek: bool,
+    pub go_next: bool,
+    pub go_previous: bool,
+    pub control: bool,
+    pub quit: bool,
+    pub raise: bool,
+}
+
+const NAMES: &[&str] = &[
+    "play",
+    "pause",
+    "seek",
+    "go_next",
+    "go_previous",
+    "control",
+    "quit",
+    "raise",
+];
+
+impl Capabilities {
+    pub fn none() -> Capabilities {
+        Capabilities {
+            play: false,
+            pause: false,
+            seek: false,
+            go_next: false,
+            go_previous: false,
+            control: false,
+            quit: false,
+            raise: false,
+        }
+    }
+
+    fn flags(&mut self) -> [&mut bool; 8] {
+        [
+            &mut self.play,
+            &mut self.pause,
+            &mut self.seek,
+            &mut self.go_next,
+            &mut self.go_previous,
+            &mut self.control,
+            &mut self.quit,
+            &mut self.raise,
+        ]
+    }
+
+    // Playback controls only make sense while the player is running and has a
+    // track loaded. Control, quit and raise describe the player itself.
+    pub fn available(self, running: bool, has_track: bool) -> Capabilities {
+        let playable = running && has_track;
+
+        Capabilities {
+            play: self.play && playable,
+            pause: self.pause && playable,
+            seek: self.seek && playable,
+            go_next: self.go_next && playable,
+            go_previous: self.go_previous && playable,
+            quit: self.quit && running,
+            ..self
+        }
+    }
+
+    pub fn encode(mut self) -> String {
+        let flags = self.flags().map(|flag| *flag);
+
+        NAMES
+            .iter()
+            .zip(flags)
+            .filter(|&(_, enabled)| enabled)
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    // Unknown names are skipped, so newer peers can announce more.
+    pub fn decode(list: &str) -> Capabilities {
+        let mut capabilities = Capabilities::none();
+
+        for name in list.split(',').map(str::trim) {
+            if let Some(index) = NAMES.iter().position(|n| *n == name) {
+                *capabilities.flags()[index] = true;
+            }
+        }
+
+        capabilities
+    }
+}
+
+// What a player that implements every control of `PlayerBackend` can do.
+impl Default for Capabilities {
+    fn default() -> Self {
+        Capabilities {
+            play: true,
+            pause: true,
+            seek: true,
+            go_next: true,
+            go_previous: true,
+            control: true,
+            quit: false,
+            raise: false,
+        }
+    }
+}
@@ -0,0 +1,250 @@
+use std::path::PathBuf;
+
+pub const USAGE: &str = "Usage: spotify-dbus-bridge [OPTIONS]
+
+Options:
+    --backend <NAME>        player backend to use (macos, mock, replay, remote, command, mirror)
+    --mock-script <FILE>    commands to run against the mock backend on startup
+    --mock-latency <MS>     delay every mock backend call by MS milliseconds
+    --benchmark <POLLS>     time POLLS polls against the mock backend and exit
+    --record <FILE>         record every polled status to FILE
+    --replay <FILE>         replay a recording (implies --backend replay)
+    --replay-speed <N>      replay speed multiplier (default 1)
+    --serve <ADDR>          serve the backend to remote bridges instead of D-Bus
+    --connect <ADDR>        use a served backend (implies --backend remote)
+    --commands <FILE>       shell commands driving the player (implies --backend command)
+    --mirror <NAME>         mirror another MPRIS player (implies --backend mirror)
+    --mirror-bus <BUS>      bus the mirrored player is on (default session)
+    --name <NAME>           export as org.mpris.MediaPlayer2.NAME (default spotify)
+    --bus <BUS>             bus to export the player on (default session)
+    --on-quit <ACTION>      what the bridge does after a Quit call, stay or exit (default stay)
+    --metadata-rules <FILE> rules for cleaning up artists, titles and albums
+    -h, --help              print this help
+
+ADDR is either host:port or unix:/path/to/socket.
+BUS is either session, system or a D-Bus address such as unix:path=/path/to/socket.
+Writing `refresh` to stdin fetches the track metadata again.";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackendKind {
+    Macos,
+    Mock,
+    Replay,
+    Remote,
+    Command,
+    Mirror,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BusKind {
+    Session,
+    System,
+    Address(String),
+}
+
+impl BusKind {
+    pub fn connect(&self) -> Result<dbus::Connection, dbus::Error> {
+        match self {
+            BusKind::Session => dbus::Connection::get_private(dbus::BusType::Session),
+            BusKind::System => dbus::Connection::get_private(dbus::BusType::System),
+            BusKind::Address(address) => {
+                let conn = dbus::Connection::open_private(address)?;
+                conn.register()?;
+                Ok(conn)
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for BusKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "session" => Ok(BusKind::Session),
+            "system" => Ok(BusKind::System),
+            _ if s.contains(':') => Ok(BusKind::Address(s.to_string())),
+            _ => Err(format!("unknown bus `{}`", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuitAction {
+    Stay,
+    Exit,
+}
+
+impl std::str::FromStr for QuitAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stay" => Ok(QuitAction::Stay),
+            "exit" => Ok(QuitAction::Exit),
+            _ => Err(format!("unknown quit action `{}`", s)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Config {
+    pub backend: BackendKind,
+    pub mock_script: Option<PathBuf>,
+    pub mock_latency: u64,
+    pub benchmark: Option<u32>,
+    pub record: Option<PathBuf>,
+    pub replay: Option<PathBuf>,
+    pub replay_speed: f64,
+    pub serve: Option<String>,
+    pub connect: Option<String>,
+    pub commands: Option<PathBuf>,
+    pub mirror: Option<String>,
+    pub mirror_bus: BusKind,
+    pub name: String,
+    pub bus: BusKind,
+    pub on_quit: QuitAction,
+    pub metadata_rules: Option<PathBuf>,
+    pub help: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            backend: BackendKind::Macos,
+            mock_script: None,
+            mock_latency: 0,
+            benchmark: None,
+            record: None,
+            replay: None,
+            replay_speed: 1.0,
+            serve: None,
+            connect: None,
+            commands: None,
+            mirror: None,
+            mirror_bus: BusKind::Session,
+            name: "spotify".to_string(),
+            bus: BusKind::Session,
+            on_quit: QuitAction::Stay,
+            metadata_rules: None,
+            help: false,
+        }
+    }
+}
+
+impl Config {
+    pub fn from_args<I: Iterator<Item = String>>(mut args: I) -> Result<Config, String> {
+        let mut config = Config::default();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--backend" => {
+                    config.backend = match value(&arg, args.next())?.as_str() {
+                        "macos" => BackendKind::Macos,
+                        "mock" => BackendKind::Mock,
+                        "replay" => BackendKind::Replay,
+                        "remote" => BackendKind::Remote,
+                        "command" => BackendKind::Command,
+                        "mirror" => BackendKind::Mirror,
+                        other => return Err(format!("unknown backend `{}`", other)),
+                    }
+                }
+                "--mock-script" => config.mock_script = Some(value(&arg, args.next())?.into()),
+                "--mock-latency" => {
+                    config.mock_latency = value(&arg, args.next())?
+                        .parse()
+                        .map_err(|_| "mock latency must be a number of milliseconds".to_string())?
+                }
+                "--benchmark" => {
+                    config.backend = BackendKind::Mock;
+                    config.benchmark = match value(&arg, args.next())?.parse::<u32>() {
+                        Ok(polls) if polls > 0 => Some(polls),
+                        _ => return Err("benchmark polls must be a positive number".to_string()),
+                    }
+                }
+                "--record" => config.record = Some(value(&arg, args.next())?.into()),
+                "--replay" => {
+                    config.backend = BackendKind::Replay;
+                    config.replay = Some(value(&arg, args.next())?.into());
+                }
+                "--replay-speed" => {
+                    config.replay_speed = match value(&arg, args.next())?.parse::<f64>() {
+                        Ok(speed) if speed > 0.0 => speed,
+                        _ => return Err("replay speed must be a positive number".to_string()),
+                    }
+                }
+                "--serve" => config.serve = Some(value(&arg, args.next())?),
+                "--connect" => {
+                    config.backend = BackendKind::Remote;
+                    config.connect = Some(value(&arg, args.next())?);
+                }
+                "--commands" => {
+                    config.backend = BackendKind::Command;
+                    config.commands = Some(value(&arg, args.next())?.into());
+                }
+                "--mirror" => {
+                    config.backend = BackendKind::Mirror;
+                    config.mirror = Some(value(&arg, args.next())?);
+                }
+                "--mirror-bus" => config.mirror_bus = value(&arg, args.next())?.parse()?,
+                "--name" => config.name = value(&arg, args.next())?,
+                "--bus" => config.bus = value(&arg, args.next())?.parse()?,
+                "--on-quit" => config.on_quit = value(&arg, args.next())?.parse()?,
+                "--metadata-rules" => {
+                    config.metadata_rules = Some(value(&arg, args.next())?.into())
+                }
+                "-h" | "--help" => config.help = true,
+                _ => return Err(format!("unknown option `{}`", arg)),
+            }
+        }
+
+        if config.backend == BackendKind::Replay && config.replay.is_none() {
+            return Err("the replay backend needs a recording, see --replay".to_string());
+        }
+
+        if config.backend == BackendKind::Remote && config.connect.is_none() {
+            return Err("the remote backend needs an address, see --connect".to_string());
+        }
+
+        if config.backend == BackendKind::Command && config.commands.is_none() {
+            return Err("the command backend needs a command file, see --commands".to_string());
+        }
+
+        if config.backend == BackendKind::Mirror {
+            match config.mirror {
+                None => return Err("the mirror backend needs a player, see --mirror".to_string()),
+                Some(ref mirror)
+                    if config.bus == config.mirror_bus
+                        && bus_name(mirror) == bus_name(&config.name) =>
+                {
+                    return Err("a player cannot mirror itself, see --name".to_string())
+                }
+                _ => {}
+            }
+        }
+
+        if config.name.is_empty()
+            || !config
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+        {
+            return Err(format!("invalid player name `{}`", config.name));
+        }
+
+        Ok(config)
+    }
+}
+
+fn value(option: &str, value: Option<String>) -> Result<String, String> {
+    value.ok_or_else(|| format!("missing value for `{}`", option))
+}
+
+// Both `vlc` and `org.mpris.MediaPlayer2.vlc` name the same player.
+pub fn bus_name(name: &str) -> String {
+    if name.starts_with("org.mpris.MediaPlayer2.") {
+        name.to_string()
+    } else {
+        format!("org.mpris.MediaPlayer2.{}", name)
+    }
+}
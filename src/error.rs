@@ -0,0 +1,91 @@
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum BridgeError {
+    NotRunning,
+    PermissionDenied,
+    Timeout,
+    Backend(io::Error),
+    DBus(dbus::Error),
+}
+
+pub type Result<T> = std::result::Result<T, BridgeError>;
+
+impl BridgeError {
+    // The name of the D-Bus error replied to a failed call, so scripts can
+    // tell failures apart without parsing messages.
+    pub fn dbus_name(&self) -> &'static str {
+        match self {
+            BridgeError::NotRunning => "org.mpris.MediaPlayer2.Error.NotRunning",
+            BridgeError::PermissionDenied => "org.mpris.MediaPlayer2.Error.PermissionDenied",
+            BridgeError::Timeout => "org.mpris.MediaPlayer2.Error.Timeout",
+            BridgeError::Backend(err) if err.kind() == io::ErrorKind::Unsupported => {
+                "org.mpris.MediaPlayer2.Error.NotSupported"
+            }
+            BridgeError::Backend(_) | BridgeError::DBus(_) => "org.freedesktop.DBus.Error.Failed",
+        }
+    }
+
+    // The other way around, for errors relayed by another bridge.
+    pub fn from_dbus_name(name: &str, message: &str) -> BridgeError {
+        match name {
+            "org.mpris.MediaPlayer2.Error.NotRunning" => BridgeError::NotRunning,
+            "org.mpris.MediaPlayer2.Error.PermissionDenied" => BridgeError::PermissionDenied,
+            "org.mpris.MediaPlayer2.Error.Timeout" => BridgeError::Timeout,
+            "org.mpris.MediaPlayer2.Error.NotSupported" => {
+                BridgeError::Backend(io::Error::new(io::ErrorKind::Unsupported, message))
+            }
+            _ => BridgeError::Backend(io::Error::other(message.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for BridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BridgeError::NotRunning => write!(f, "the player is not running"),
+            BridgeError::PermissionDenied => write!(f, "not allowed to control the player"),
+            BridgeError::Timeout => write!(f, "the player did not answer in time"),
+            BridgeError::Backend(err) => write!(f, "{}", err),
+            BridgeError::DBus(err) => write!(
+                f,
+                "D-Bus error: {}",
+                err.message().or_else(|| err.name()).unwrap_or("unknown")
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BridgeError {}
+
+// The Apple Events client reports everything as OS errors, -600 and -609 when
+// Spotify is not running, -1743 when the user did not allow automation and
+// -1712 when Spotify did not reply.
+impl From<io::Error> for BridgeError {
+    fn from(err: io::Error) -> Self {
+        match err.raw_os_error() {
+            Some(-600) | Some(-609) => return BridgeError::NotRunning,
+            Some(-1743) => return BridgeError::PermissionDenied,
+            Some(-1712) => return BridgeError::Timeout,
+            _ => {}
+        }
+
+        match err.kind() {
+            io::ErrorKind::PermissionDenied => BridgeError::PermissionDenied,
+            io::ErrorKind::TimedOut => BridgeError::Timeout,
+            _ => BridgeError::Backend(err),
+        }
+    }
+}
+
+impl From<dbus::Error> for BridgeError {
+    fn from(err: dbus::Error) -> Self {
+        match err.name() {
+            Some(name) if name.starts_with("org.mpris.MediaPlayer2.Error.") => {
+                BridgeError::from_dbus_name(name, err.message().unwrap_or(name))
+            }
+            _ => BridgeError::DBus(err),
+        }
+    }
+}
@@ -1,43 +1,68 @@
+#[cfg(target_os = "macos")]
+extern crate macos_open;
+#[cfg(target_os = "macos")]
 extern crate macos_spotify;
 
+mod backend;
+mod bench;
+mod cache;
+mod capabilities;
+mod config;
+mod error;
 mod mpris;
+mod record;
+mod remote;
+mod rules;
+mod snapshot;
 mod status;
+mod uri;
 mod util;
 
-use macos_spotify::Spotify as SpotifyClient;
+use backend::{
+    CommandBackend, MirrorBackend, MockBackend, PlayerBackend, RemoteBackend, ReplayBackend,
+};
+use config::{BackendKind, Config};
+use error::BridgeError;
 use mpris::Mpris;
+use record::Recorder;
+use rules::MetadataRules;
 use status::SpotifyStatus;
 use std::sync::Arc;
 
-pub struct AppState {
-    client: SpotifyClient,
+pub struct AppState<B: PlayerBackend> {
+    backend: B,
     spotify_status: SpotifyStatus,
-    mpris: Mpris,
+    mpris: Mpris<B>,
+    recorder: Option<Recorder>,
 }
 
-impl AppState {
-    pub fn new() -> AppState {
+impl<B: PlayerBackend> AppState<B> {
+    pub fn new(backend: B, recorder: Option<Recorder>, rules: MetadataRules) -> AppState<B> {
         AppState {
-            client: SpotifyClient::new(),
-            spotify_status: Default::default(),
+            backend,
+            spotify_status: SpotifyStatus::new(rules),
             mpris: Mpris::new(),
+            recorder,
         }
     }
 
-    pub fn client(&self) -> &SpotifyClient {
-        &self.client
+    pub fn backend(&self) -> &B {
+        &self.backend
     }
 
     pub fn spotify_status(&self) -> &SpotifyStatus {
         &self.spotify_status
     }
 
-    pub fn mpris(&self) -> &Mpris {
+    pub fn mpris(&self) -> &Mpris<B> {
         &self.mpris
     }
 
-    pub fn update(&self) -> std::io::Result<()> {
-        self.spotify_status.update(&self.client)?;
+    pub fn update(&self) -> error::Result<()> {
+        self.spotify_status.update(&self.backend)?;
+        if let Some(ref recorder) = self.recorder {
+            recorder.record(&self.spotify_status.snapshot())?;
+        }
         if self.has_changed() {
             self.mpris.update();
         }
@@ -54,9 +79,6 @@ impl AppState {
     }
 }
 
-unsafe impl Send for AppState {}
-unsafe impl Sync for AppState {}
-
 fn readln() -> String {
     use std::io::{self, BufRead};
 
@@ -66,17 +88,185 @@ fn readln() -> String {
     line
 }
 
-fn main() {
-    let state = Arc::new(AppState::new());
+fn exit_on_error<T>(res: std::io::Result<T>, what: &std::path::Path) -> T {
+    match res {
+        Ok(value) => value,
+        Err(err) => {
+            eprintln!("{}: {}", what.display(), err);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn start<B: PlayerBackend>(backend: B, config: &Config) -> Arc<AppState<B>> {
+    let recorder = config
+        .record
+        .as_ref()
+        .map(|path| exit_on_error(Recorder::create(path), path));
+    let rules = match config.metadata_rules {
+        Some(ref path) => exit_on_error(MetadataRules::open(path), path),
+        None => MetadataRules::default(),
+    };
+    let state = Arc::new(AppState::new(backend, recorder, rules));
+
+    if config.serve.is_none() {
+        state.mpris().run(
+            state.clone(),
+            config.name.clone(),
+            config.bus.clone(),
+            config.on_quit,
+        );
+    }
+
+    state
+}
+
+fn poll<B: PlayerBackend>(state: Arc<AppState<B>>, config: &Config) {
+    if let Some(ref addr) = config.serve {
+        if let Err(err) = remote::serve(state, addr) {
+            eprintln!("{}: {}", addr, err);
+            std::process::exit(1);
+        }
+        return;
+    }
 
-    state.mpris().run(state.clone());
+    let mut last_error = None;
 
     loop {
-        if let Err(err) = state.update() {
-            println!("{}", err);
+        let error = match state.update() {
+            Ok(()) => None,
+            Err(BridgeError::PermissionDenied) => Some(
+                "Not allowed to control Spotify, allow it in System Preferences > \
+                 Security & Privacy > Privacy > Automation"
+                    .to_string(),
+            ),
+            Err(err) => Some(err.to_string()),
+        };
+
+        // Errors usually last for many polls, only report when they change.
+        if error != last_error {
+            if let Some(ref error) = error {
+                println!("{}", error);
+            }
+            last_error = error;
         }
 
         // readln();
-        std::thread::sleep(std::time::Duration::from_millis(400));
+        state.backend().wait();
+    }
+}
+
+fn run_mock(config: &Config) {
+    let backend = MockBackend::new();
+    backend.set_latency(std::time::Duration::from_millis(config.mock_latency));
+
+    if let Some(ref path) = config.mock_script {
+        let script = exit_on_error(std::fs::read_to_string(path), path);
+
+        for line in script.lines() {
+            if let Err(err) = backend.execute(line) {
+                eprintln!("{}: {}", line, err);
+            }
+        }
+    }
+
+    if let Some(polls) = config.benchmark {
+        if let Err(err) = bench::run(backend, polls) {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let state = start(backend, config);
+    read_stdin(state.clone(), |backend: &MockBackend, line| {
+        backend.execute(line)
+    });
+    poll(state, config);
+}
+
+fn run<B: PlayerBackend>(backend: B, config: &Config) {
+    let state = start(backend, config);
+    read_stdin(state.clone(), |_, _| {
+        Err(BridgeError::Backend(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "unknown command",
+        )))
+    });
+    poll(state, config);
+}
+
+// `refresh` on stdin fetches the track metadata again on the next poll, other
+// lines go to `execute`.
+fn read_stdin<B, F>(state: Arc<AppState<B>>, execute: F)
+where
+    B: PlayerBackend,
+    F: Fn(&B, &str) -> error::Result<()> + Send + 'static,
+{
+    std::thread::spawn(move || loop {
+        let line = readln();
+        if line.is_empty() {
+            break;
+        }
+
+        if line.trim() == "refresh" {
+            state.spotify_status().refresh_metadata();
+            continue;
+        }
+
+        if let Err(err) = execute(state.backend(), &line) {
+            eprintln!("{}: {}", line.trim(), err);
+        }
+    });
+}
+
+fn main() {
+    let config = match Config::from_args(std::env::args().skip(1)) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, config::USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    if config.help {
+        println!("{}", config::USAGE);
+        return;
+    }
+
+    match config.backend {
+        #[cfg(target_os = "macos")]
+        BackendKind::Macos => run(backend::MacosBackend::new(), &config),
+        #[cfg(not(target_os = "macos"))]
+        BackendKind::Macos => {
+            eprintln!("The macos backend is not available on this platform");
+            std::process::exit(1);
+        }
+        BackendKind::Mock => run_mock(&config),
+        BackendKind::Replay => {
+            let path = config.replay.as_ref().unwrap();
+            let backend = exit_on_error(ReplayBackend::open(path, config.replay_speed), path);
+            run(backend, &config);
+        }
+        BackendKind::Remote => {
+            let backend = RemoteBackend::connect(config.connect.as_ref().unwrap());
+            run(backend, &config);
+        }
+        BackendKind::Command => {
+            let path = config.commands.as_ref().unwrap();
+            let backend = exit_on_error(CommandBackend::open(path), path);
+            run(backend, &config);
+        }
+        BackendKind::Mirror => {
+            let name = config.mirror.as_ref().unwrap();
+            let backend = match MirrorBackend::connect(name, &config.mirror_bus) {
+                Ok(backend) => backend,
+                Err(err) => {
+                    eprintln!("{}: {}", name, err);
+                    std::process::exit(1);
+                }
+            };
+            run(backend, &config);
+        }
     }
 }
@@ -1,58 +1,84 @@
+use std::cell::Cell;
+use std::fmt;
+use std::io;
 use std::rc::Rc;
 use std::sync::{
     mpsc::{channel, Receiver, Sender},
     Arc, Mutex, RwLock,
 };
-use std::thread::{spawn, JoinHandle};
+use std::thread::{sleep, spawn, JoinHandle};
+use std::time::{Duration, Instant};
 
 use std::collections::HashMap;
 
+use crate::backend::PlayerBackend;
+use crate::config::{bus_name, BusKind, QuitAction};
+use crate::error::{self, BridgeError};
 use crate::AppState;
 
-use crate::status::PlaybackStatus;
+use crate::status::{PlaybackStatus, RepeatMode, Track};
+use crate::uri::{SpotifyUri, UriKind};
 
 use dbus::arg::{RefArg, Variant};
 use dbus::stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged;
-use dbus::tree::{Access, Factory};
-use dbus::{Path, SignalArgs};
+use dbus::tree::{Access, Factory, MethodErr};
+use dbus::{Connection, Message, Path, SignalArgs};
+
+// How long a written property may take to show up when read back.
+const CONFIRM_TIMEOUT: Duration = Duration::from_secs(2);
+const CONFIRM_INTERVAL: Duration = Duration::from_millis(50);
 
 #[derive(PartialEq, Eq)]
 enum MprisCommand {
     Ok,
     Tick,
+    #[allow(dead_code)]
     Stop,
 }
 
-struct MprisInternal {
-    app_state: Arc<AppState>,
+struct MprisInternal<B: PlayerBackend> {
+    app_state: Arc<AppState<B>>,
+    #[allow(dead_code)]
     handle: JoinHandle<()>,
     tx: Sender<MprisCommand>,
-    rx: Receiver<MprisCommand>,
+    rx: Mutex<Receiver<MprisCommand>>,
 }
 
-impl MprisInternal {
-    pub fn new(state: Arc<AppState>) -> MprisInternal {
+impl<B: PlayerBackend> MprisInternal<B> {
+    pub fn new(
+        state: Arc<AppState<B>>,
+        name: String,
+        bus: BusKind,
+        on_quit: QuitAction,
+    ) -> MprisInternal<B> {
         let (tx1, rx1) = channel::<MprisCommand>();
         let (tx2, rx2) = channel::<MprisCommand>();
 
         let moving_state = state.clone();
 
         let handle = spawn(move || {
-            run_server(moving_state, tx2, rx1);
+            match run_server(moving_state, name, bus, on_quit, tx2, rx1) {
+                // The server only returns once a Quit call asked the bridge to exit.
+                Ok(()) => std::process::exit(0),
+                Err(err) => {
+                    eprintln!("Cannot serve MPRIS: {}", err);
+                    std::process::exit(1);
+                }
+            }
         });
 
         MprisInternal {
             app_state: state,
             handle,
             tx: tx1,
-            rx: rx2,
+            rx: Mutex::new(rx2),
         }
     }
 
     pub fn update(&self) {
         self.tx.send(MprisCommand::Tick).unwrap();
         loop {
-            if let Ok(msg) = self.rx.recv() {
+            if let Ok(msg) = self.rx.lock().unwrap().recv() {
                 if msg == MprisCommand::Ok {
                     {
                         let mut x = self.app_state.mpris().locker.lock().unwrap();
@@ -66,22 +92,22 @@ impl MprisInternal {
     }
 }
 
-pub struct Mpris {
-    inner: RwLock<Option<MprisInternal>>,
+pub struct Mpris<B: PlayerBackend> {
+    inner: RwLock<Option<MprisInternal<B>>>,
     locker: Mutex<()>,
 }
 
-impl Mpris {
-    pub fn new() -> Mpris {
+impl<B: PlayerBackend> Mpris<B> {
+    pub fn new() -> Mpris<B> {
         Mpris {
             inner: RwLock::new(None),
             locker: Mutex::new(()),
         }
     }
 
-    pub fn run(&self, state: Arc<AppState>) {
+    pub fn run(&self, state: Arc<AppState<B>>, name: String, bus: BusKind, on_quit: QuitAction) {
         if !self.is_running() {
-            let inner = MprisInternal::new(state);
+            let inner = MprisInternal::new(state, name, bus, on_quit);
 
             {
                 let mut v = self.inner.write().unwrap();
@@ -101,178 +127,371 @@ impl Mpris {
     }
 }
 
-unsafe impl Send for Mpris {}
-unsafe impl Sync for Mpris {}
-
-fn get_metadata(state: Arc<AppState>) -> HashMap<String, Variant<Box<RefArg>>> {
-    let mut hm: HashMap<String, Variant<Box<RefArg>>> = HashMap::new();
+fn get_metadata<B: PlayerBackend>(
+    state: Arc<AppState<B>>,
+) -> HashMap<String, Variant<Box<dyn RefArg>>> {
+    let mut hm: HashMap<String, Variant<Box<dyn RefArg>>> = HashMap::new();
 
     let track = state.spotify_status().track();
 
     hm.insert(
         "mpris:trackid".to_string(),
-        Variant(Box::new(
-            track
-                .id()
-                .as_ref()
-                .as_ref()
-                .map(|v| v.clone())
-                .unwrap_or_default(),
-        )),
+        Variant(Box::new(current_path(&track))),
     );
 
-    hm.insert(
-        "mpris:length".to_string(),
-        Variant(Box::new(
-            track
-                .duration()
-                .as_ref()
-                .as_ref()
-                .map(|v| v * 1_000)
-                .unwrap_or_default(),
-        )),
-    );
+    // Without a track the spec wants nothing but the NoTrack id.
+    if track.id().is_none() && track.name().is_none() {
+        return hm;
+    }
 
-    hm.insert(
-        "mpris:artUrl".to_string(),
-        Variant(Box::new(
-            track
-                .artwork_url()
-                .as_ref()
-                .as_ref()
-                .map(|v| v.clone())
-                .unwrap_or_default(),
-        )),
-    );
+    if let Some(duration) = *track.duration() {
+        hm.insert(
+            "mpris:length".to_string(),
+            Variant(Box::new(i64::from(duration) * 1_000)),
+        );
+    }
 
-    hm.insert(
-        "xesam:album".to_string(),
-        Variant(Box::new(
-            track
-                .album()
-                .as_ref()
-                .as_ref()
-                .map(|v| v.clone())
-                .unwrap_or_default(),
-        )),
-    );
+    if let Some(url) = known(track.artwork_url()) {
+        hm.insert("mpris:artUrl".to_string(), Variant(Box::new(url)));
+    }
 
-    hm.insert(
-        "xesam:albumArtist".to_string(),
-        Variant(Box::new(
-            track
-                .album_artist()
-                .as_ref()
-                .as_ref()
-                .map(|v| v.clone())
-                .unwrap_or_default(),
-        )),
-    );
+    if let Some(album) = known(track.clean_album()) {
+        hm.insert("xesam:album".to_string(), Variant(Box::new(album)));
+    }
 
-    hm.insert(
-        "xesam:artist".to_string(),
-        Variant(Box::new(
-            track
-                .artist()
-                .as_ref()
-                .as_ref()
-                .map(|v| v.clone())
-                .unwrap_or_default(),
-        )),
-    );
+    let album_artists = track.album_artists();
+    if !album_artists.is_empty() {
+        hm.insert(
+            "xesam:albumArtist".to_string(),
+            Variant(Box::new(album_artists.as_ref().clone())),
+        );
+    }
 
-    hm.insert(
-        "xesam:discNumber".to_string(),
-        Variant(Box::new(
-            track
-                .disk_number()
-                .as_ref()
-                .as_ref()
-                .map(|v| *v)
-                .unwrap_or_default(),
-        )),
-    );
+    let artists = track.artists();
+    if !artists.is_empty() {
+        hm.insert(
+            "xesam:artist".to_string(),
+            Variant(Box::new(artists.as_ref().clone())),
+        );
+    }
 
-    hm.insert(
-        "xesam:title".to_string(),
-        Variant(Box::new(
-            track
-                .name()
-                .as_ref()
-                .as_ref()
-                .map(|v| v.clone())
-                .unwrap_or_default(),
-        )),
-    );
+    if let Some(disk_number) = *track.disk_number() {
+        hm.insert(
+            "xesam:discNumber".to_string(),
+            Variant(Box::new(disk_number)),
+        );
+    }
 
-    hm.insert("xesam:trackNumber".to_string(), Variant(Box::new(0)));
+    if let Some(track_number) = *track.track_number() {
+        hm.insert(
+            "xesam:trackNumber".to_string(),
+            Variant(Box::new(track_number)),
+        );
+    }
 
-    hm.insert(
-        "xesam:url".to_string(),
-        Variant(Box::new(
-            track
-                .url()
-                .as_ref()
-                .as_ref()
-                .map(|v| v.clone())
-                .unwrap_or_default(),
-        )),
-    );
+    if let Some(played_count) = *track.played_count() {
+        hm.insert(
+            "xesam:useCount".to_string(),
+            Variant(Box::new(played_count)),
+        );
+    }
+
+    // Spotify rates popularity from 0 to 100, the spec from 0 to 1.
+    if let Some(popularity) = *track.popularity() {
+        hm.insert(
+            "xesam:autoRating".to_string(),
+            Variant(Box::new(f64::from(popularity.clamp(0, 100)) / 100.0)),
+        );
+    }
+
+    if let Some(explicit) = *track.explicit() {
+        hm.insert(
+            "spotifybridge:explicit".to_string(),
+            Variant(Box::new(explicit)),
+        );
+    }
+
+    if let Some(starred) = *track.starred() {
+        hm.insert(
+            "spotifybridge:starred".to_string(),
+            Variant(Box::new(starred)),
+        );
+    }
+
+    if let Some(name) = known(track.clean_name()) {
+        hm.insert("xesam:title".to_string(), Variant(Box::new(name)));
+    }
+
+    // What the player reported, before the metadata rules cleaned it up.
+    if let Some(name) = known(track.name()) {
+        hm.insert(
+            "spotifybridge:rawTitle".to_string(),
+            Variant(Box::new(name)),
+        );
+    }
+
+    if let Some(album) = known(track.album()) {
+        hm.insert(
+            "spotifybridge:rawAlbum".to_string(),
+            Variant(Box::new(album)),
+        );
+    }
+
+    if let Some(artist) = known(track.artist()) {
+        hm.insert(
+            "spotifybridge:rawArtist".to_string(),
+            Variant(Box::new(artist)),
+        );
+    }
+
+    if let Some(url) = known(track.url()).or_else(|| track.uri().map(|uri| uri.url())) {
+        hm.insert("xesam:url".to_string(), Variant(Box::new(url)));
+    }
 
     hm
 }
 
-fn get_playbackstatus(state: Arc<AppState>) -> String {
+// Players report some unknown values as empty strings.
+fn known(value: Arc<Option<String>>) -> Option<String> {
+    value.as_deref().filter(|v| !v.is_empty()).map(String::from)
+}
+
+fn get_playbackstatus<B: PlayerBackend>(state: Arc<AppState<B>>) -> String {
     match state.spotify_status().playback_status() {
-        PlaybackStatus::STOPPED => "Stopped",
-        PlaybackStatus::PLAYING => "Playing",
-        PlaybackStatus::PAUSED => "Paused",
+        PlaybackStatus::Stopped => "Stopped",
+        PlaybackStatus::Playing => "Playing",
+        PlaybackStatus::Paused => "Paused",
     }
     .to_string()
 }
 
-fn get_loopstatus(state: Arc<AppState>) -> String {
-    match state.spotify_status().is_repeating() {
-        None | Some(false) => "None",
-        Some(true) => "Playlist",
+fn get_loopstatus<B: PlayerBackend>(state: Arc<AppState<B>>) -> String {
+    match state.spotify_status().repeat_mode() {
+        None | Some(RepeatMode::Off) => "None",
+        Some(RepeatMode::Context) => "Playlist",
+        Some(RepeatMode::Track) => "Track",
     }
     .to_string()
 }
 
-fn get_shuffle(state: Arc<AppState>) -> bool {
+fn get_position<B: PlayerBackend>(state: Arc<AppState<B>>) -> i64 {
+    state
+        .spotify_status()
+        .position()
+        .map(|v| (v * 1_000_000.0).round() as i64)
+        .unwrap_or_default()
+}
+
+fn get_volume<B: PlayerBackend>(state: Arc<AppState<B>>) -> f64 {
+    state
+        .spotify_status()
+        .volume()
+        .map(|v| (v as f64) / 100.0)
+        .unwrap_or_default()
+}
+
+fn get_shuffle<B: PlayerBackend>(state: Arc<AppState<B>>) -> bool {
     match state.spotify_status().is_shuffling() {
         None | Some(false) => false,
         Some(true) => true,
     }
 }
 
-fn run_server(state: Arc<AppState>, tx: Sender<MprisCommand>, rx: Receiver<MprisCommand>) {
-    let conn = Rc::new(
-        dbus::Connection::get_private(dbus::BusType::Session).expect("Failed to connect to dbus"),
-    );
-    conn.register_name(
-        "org.mpris.MediaPlayer2.spotify",
-        dbus::NameFlag::ReplaceExisting as u32,
-    )
-    .expect("Failed to register dbus player name");
+// Spotify ids are not valid object paths, so URIs become paths the way
+// Spotify's own client names them, `/com/spotify/track/<id>`, and anything
+// else is escaped. Ids of mirrored players already are paths. The spec keeps
+// `/org/mpris` for itself.
+fn track_path(id: &str) -> Path<'static> {
+    if let Ok(path) = Path::new(id) {
+        return path;
+    }
+
+    let (kind, id) = match SpotifyUri::parse(id) {
+        Some(uri) => (uri.kind.as_str(), uri.id),
+        None => ("id", id.to_string()),
+    };
+
+    Path::new(format!("/com/spotify/{}/{}", kind, escape(&id))).unwrap_or_else(|_| no_track())
+}
+
+// Tracks without an id are named by their title and length, which stay put
+// while they play. NoTrack is kept for when nothing plays.
+fn current_path(track: &Track) -> Path<'static> {
+    if let Some(id) = track.id().as_deref() {
+        return track_path(id);
+    }
+
+    match track.name().as_deref() {
+        Some(name) => Path::new(format!(
+            "/com/spotify/title/{}_{}",
+            escape(name),
+            track.duration().unwrap_or_default()
+        ))
+        .unwrap_or_else(|_| no_track()),
+        None => no_track(),
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+fn no_track() -> Path<'static> {
+    Path::new("/org/mpris/MediaPlayer2/TrackList/NoTrack").unwrap()
+}
+
+fn track_length<B: PlayerBackend>(state: &AppState<B>) -> Option<i64> {
+    state
+        .spotify_status()
+        .track()
+        .duration()
+        .map(|v| i64::from(v) * 1_000)
+}
+
+// Seeking before the start restarts the track, seeking past its end skips it.
+fn seek<B: PlayerBackend>(state: &AppState<B>, offset: i64) -> error::Result<()> {
+    let status = state.spotify_status();
+    if !status.capabilities().seek {
+        return Ok(());
+    }
+
+    let position = status
+        .position()
+        .map(|v| (v * 1_000_000.0).round() as i64)
+        .unwrap_or_default()
+        .saturating_add(offset);
+
+    match track_length(state) {
+        Some(length) if position > length => state.backend().next(),
+        _ => state
+            .backend()
+            .set_position(position.max(0) as f64 / 1_000_000.0),
+    }
+}
+
+// Pauses back at the start of the track, and reports stopped until playback resumes.
+fn stop<B: PlayerBackend>(state: &AppState<B>) -> error::Result<()> {
+    let status = state.spotify_status();
+
+    state.backend().pause()?;
+    if status.capabilities().seek {
+        state.backend().set_position(0.0)?;
+    }
+    status.stop();
+
+    Ok(())
+}
+
+// Calls for another track than the current one are stale and ignored, as are
+// positions outside of the track.
+fn set_position<B: PlayerBackend>(
+    state: &AppState<B>,
+    trackid: &Path,
+    position: i64,
+) -> error::Result<()> {
+    let status = state.spotify_status();
+    if !status.capabilities().seek {
+        return Ok(());
+    }
+
+    if current_path(&status.track()) != *trackid {
+        return Ok(());
+    }
+
+    if position < 0 || track_length(state).is_some_and(|length| position > length) {
+        return Ok(());
+    }
+
+    state.backend().set_position(position as f64 / 1_000_000.0)
+}
+
+// What OpenUri plays, artists and shows have no obvious first track.
+const OPEN_KINDS: &[UriKind] = &[
+    UriKind::Track,
+    UriKind::Album,
+    UriKind::Playlist,
+    UriKind::Episode,
+];
+
+// Polls a field the bridge just wrote until the player reports a value it
+// accepts, so clients never hear about a change that did not happen.
+fn confirm<B, T, R, A>(backend: &B, field: &str, read: R, accept: A) -> error::Result<T>
+where
+    B: PlayerBackend,
+    T: Copy + fmt::Display,
+    R: Fn(&B) -> error::Result<Option<T>>,
+    A: Fn(T) -> bool,
+{
+    let deadline = Instant::now() + CONFIRM_TIMEOUT;
+
+    loop {
+        backend.invalidate();
+        let value = read(backend)?;
+        match value {
+            Some(value) if accept(value) => return Ok(value),
+            _ if Instant::now() >= deadline => {
+                let value = value.map_or("nothing".to_string(), |v| v.to_string());
+                return Err(BridgeError::Backend(io::Error::other(format!(
+                    "the player kept its {} at {}",
+                    field, value
+                ))));
+            }
+            _ => sleep(CONFIRM_INTERVAL),
+        }
+    }
+}
+
+fn emit_changed<T: RefArg + 'static>(conn: &Connection, name: &str, value: T) {
+    let mut changed = PropertiesPropertiesChanged {
+        interface_name: "org.mpris.MediaPlayer2.Player".to_string(),
+        ..Default::default()
+    };
+    changed
+        .changed_properties
+        .insert(name.to_string(), Variant(Box::new(value)));
+
+    conn.send(changed.to_emit_message(&Path::new("/org/mpris/MediaPlayer2".to_string()).unwrap()))
+        .unwrap();
+}
+
+fn method_err(err: BridgeError) -> MethodErr {
+    MethodErr::from((err.dbus_name(), err.to_string()))
+}
+
+fn run_server<B: PlayerBackend>(
+    state: Arc<AppState<B>>,
+    name: String,
+    bus: BusKind,
+    on_quit: QuitAction,
+    tx: Sender<MprisCommand>,
+    rx: Receiver<MprisCommand>,
+) -> error::Result<()> {
+    let conn = Rc::new(bus.connect()?);
+    conn.register_name(&bus_name(&name), dbus::NameFlag::ReplaceExisting as u32)?;
 
     let f = Factory::new_fn::<()>();
 
-    let property_canquit = f
-        .property::<bool, _>("CanQuit", ())
-        .access(Access::Read)
-        .on_get(|iter, _| {
-            iter.append(false); // TODO
-            Ok(())
-        });
+    let property_canquit = {
+        let state = state.clone();
+        f.property::<bool, _>("CanQuit", ())
+            .access(Access::Read)
+            .on_get(move |iter, _| {
+                iter.append(state.spotify_status().capabilities().quit);
+                Ok(())
+            })
+    };
 
-    let property_canraise = f
-        .property::<bool, _>("CanRaise", ())
-        .access(Access::Read)
-        .on_get(|iter, _| {
-            iter.append(false); // TODO
-            Ok(())
-        });
+    let property_canraise = {
+        let state = state.clone();
+        f.property::<bool, _>("CanRaise", ())
+            .access(Access::Read)
+            .on_get(move |iter, _| {
+                iter.append(state.spotify_status().capabilities().raise);
+                Ok(())
+            })
+    };
 
     let property_cansetfullscreen = f
         .property::<bool, _>("CanSetFullscreen", ())
@@ -290,11 +509,15 @@ fn run_server(state: Arc<AppState>, tx: Sender<MprisCommand>, rx: Receiver<Mpris
             Ok(())
         });
 
+    let identity = name
+        .strip_prefix("org.mpris.MediaPlayer2.")
+        .unwrap_or(&name)
+        .to_string();
     let property_identity = f
         .property::<String, _>("Identity", ())
         .access(Access::Read)
-        .on_get(|iter, _| {
-            iter.append("spotify".to_string());
+        .on_get(move |iter, _| {
+            iter.append(identity.clone());
             Ok(())
         });
 
@@ -302,7 +525,7 @@ fn run_server(state: Arc<AppState>, tx: Sender<MprisCommand>, rx: Receiver<Mpris
         .property::<Vec<String>, _>("SupportedUriSchemes", ())
         .access(Access::Read)
         .on_get(|iter, _| {
-            iter.append(vec!["spotify".to_string()]);
+            iter.append(vec!["spotify".to_string(), "https".to_string()]);
             Ok(())
         });
 
@@ -314,6 +537,27 @@ fn run_server(state: Arc<AppState>, tx: Sender<MprisCommand>, rx: Receiver<Mpris
             Ok(())
         });
 
+    let method_raise = {
+        let state = state.clone();
+        f.method("Raise", (), move |m| {
+            state.backend().raise().map_err(method_err)?;
+            Ok(vec![m.msg.method_return()])
+        })
+    };
+
+    let exiting = Rc::new(Cell::new(false));
+
+    let method_quit = {
+        let state = state.clone();
+        let exiting = exiting.clone();
+        f.method("Quit", (), move |m| {
+            state.backend().quit().map_err(method_err)?;
+            state.spotify_status().not_running(state.backend());
+            exiting.set(on_quit == QuitAction::Exit);
+            Ok(vec![m.msg.method_return()])
+        })
+    };
+
     let interface = f
         .interface("org.mpris.MediaPlayer2", ())
         .add_p(property_canquit)
@@ -322,7 +566,9 @@ fn run_server(state: Arc<AppState>, tx: Sender<MprisCommand>, rx: Receiver<Mpris
         .add_p(property_hastracklist)
         .add_p(property_identity)
         .add_p(property_urischemes)
-        .add_p(property_mimetypes);
+        .add_p(property_mimetypes)
+        .add_m(method_quit)
+        .add_m(method_raise);
 
     let property_playbackstatus = {
         let state = state.clone();
@@ -337,29 +583,41 @@ fn run_server(state: Arc<AppState>, tx: Sender<MprisCommand>, rx: Receiver<Mpris
     let property_loopstatus = {
         let state = state.clone();
         let state2 = state.clone();
+        let conn = conn.clone();
         f.property::<String, _>("LoopStatus", ())
             .access(Access::ReadWrite)
+            .auto_emit_on_set(false)
             .on_get(move |iter, _| {
                 iter.append(get_loopstatus(state.clone()));
                 Ok(())
             })
             .on_set(move |iter, _| {
-                match iter.get() {
-                    Some("None") => {
-                        state2.client().set_repeating(false);
-                    }
-                    Some("Playlist") => {
-                        state2.client().set_repeating(true);
-                    }
-                    _ => {}
+                let mode = match iter.read::<&str>()? {
+                    "None" => RepeatMode::Off,
+                    "Playlist" => RepeatMode::Context,
+                    "Track" => RepeatMode::Track,
+                    value => return Err(MethodErr::invalid_arg(&value)),
                 };
+
+                let backend = state2.backend();
+                backend.set_repeat_mode(mode).map_err(method_err)?;
+                let mode = confirm(
+                    backend,
+                    "repeat mode",
+                    |backend| backend.repeat_mode(),
+                    |v| v == mode,
+                )
+                .map_err(method_err)?;
+
+                state2.spotify_status().set_repeat_mode(Some(mode));
+                emit_changed(&conn, "LoopStatus", get_loopstatus(state2.clone()));
                 Ok(())
             })
     };
 
     let property_metadata = {
         let state = state.clone();
-        f.property::<HashMap<String, Variant<Box<RefArg>>>, _>("Metadata", ())
+        f.property::<HashMap<String, Variant<Box<dyn RefArg>>>, _>("Metadata", ())
             .access(Access::Read)
             .on_get(move |iter, _| {
                 iter.append(get_metadata(state.clone()));
@@ -372,13 +630,7 @@ fn run_server(state: Arc<AppState>, tx: Sender<MprisCommand>, rx: Receiver<Mpris
         f.property::<i64, _>("Position", ())
             .access(Access::Read)
             .on_get(move |iter, _| {
-                iter.append(
-                    state
-                        .spotify_status()
-                        .position()
-                        .map(|v| (v * 1_000_000.0).round() as i64)
-                        .unwrap_or_default(),
-                );
+                iter.append(get_position(state.clone()));
                 Ok(())
             })
     };
@@ -386,22 +638,30 @@ fn run_server(state: Arc<AppState>, tx: Sender<MprisCommand>, rx: Receiver<Mpris
     let property_volume = {
         let state = state.clone();
         let state2 = state.clone();
+        let conn = conn.clone();
         f.property::<f64, _>("Volume", ())
             .access(Access::ReadWrite)
+            .auto_emit_on_set(false)
             .on_get(move |iter, _| {
-                iter.append(
-                    state
-                        .spotify_status()
-                        .volume()
-                        .map(|v| (v as f64) / 100.0)
-                        .unwrap_or_default(),
-                );
+                iter.append(get_volume(state.clone()));
                 Ok(())
             })
             .on_set(move |iter, _| {
-                if let Some(vol) = iter.get::<f64>() {
-                    state2.client().set_volume((vol * 100.0).round() as i32);
-                }
+                let volume = (iter.read::<f64>()?.clamp(0.0, 1.0) * 100.0).round() as i32;
+
+                let backend = state2.backend();
+                backend.set_volume(volume).map_err(method_err)?;
+                // Spotify often settles one step below the volume it was given.
+                let volume = confirm(
+                    backend,
+                    "volume",
+                    |backend| backend.volume(),
+                    |v| (v - volume).abs() <= 1,
+                )
+                .map_err(method_err)?;
+
+                state2.spotify_status().set_volume(Some(volume));
+                emit_changed(&conn, "Volume", get_volume(state2.clone()));
                 Ok(())
             })
     };
@@ -430,67 +690,92 @@ fn run_server(state: Arc<AppState>, tx: Sender<MprisCommand>, rx: Receiver<Mpris
             Ok(())
         });
 
-    let property_canplay = f
-        .property::<bool, _>("CanPlay", ())
-        .access(Access::Read)
-        .on_get(|iter, _| {
-            iter.append(true);
-            Ok(())
-        });
+    let property_canplay = {
+        let state = state.clone();
+        f.property::<bool, _>("CanPlay", ())
+            .access(Access::Read)
+            .on_get(move |iter, _| {
+                iter.append(state.spotify_status().capabilities().play);
+                Ok(())
+            })
+    };
 
-    let property_canpause = f
-        .property::<bool, _>("CanPause", ())
-        .access(Access::Read)
-        .on_get(|iter, _| {
-            iter.append(true);
-            Ok(())
-        });
+    let property_canpause = {
+        let state = state.clone();
+        f.property::<bool, _>("CanPause", ())
+            .access(Access::Read)
+            .on_get(move |iter, _| {
+                iter.append(state.spotify_status().capabilities().pause);
+                Ok(())
+            })
+    };
 
-    let property_canseek = f
-        .property::<bool, _>("CanSeek", ())
-        .access(Access::Read)
-        .on_get(|iter, _| {
-            iter.append(false); // TODO
-            Ok(())
-        });
+    let property_canseek = {
+        let state = state.clone();
+        f.property::<bool, _>("CanSeek", ())
+            .access(Access::Read)
+            .on_get(move |iter, _| {
+                iter.append(state.spotify_status().capabilities().seek);
+                Ok(())
+            })
+    };
 
-    let property_cancontrol = f
-        .property::<bool, _>("CanControl", ())
-        .access(Access::Read)
-        .on_get(|iter, _| {
-            iter.append(true);
-            Ok(())
-        });
+    let property_cancontrol = {
+        let state = state.clone();
+        f.property::<bool, _>("CanControl", ())
+            .access(Access::Read)
+            .on_get(move |iter, _| {
+                iter.append(state.spotify_status().capabilities().control);
+                Ok(())
+            })
+    };
 
-    let property_cangonext = f
-        .property::<bool, _>("CanGoNext", ())
-        .access(Access::Read)
-        .on_get(|iter, _| {
-            iter.append(false);
-            Ok(())
-        });
+    let property_cangonext = {
+        let state = state.clone();
+        f.property::<bool, _>("CanGoNext", ())
+            .access(Access::Read)
+            .on_get(move |iter, _| {
+                iter.append(state.spotify_status().capabilities().go_next);
+                Ok(())
+            })
+    };
 
-    let property_cangoprevious = f
-        .property::<bool, _>("CanGoPrevious", ())
-        .access(Access::Read)
-        .on_get(|iter, _| {
-            iter.append(false);
-            Ok(())
-        });
+    let property_cangoprevious = {
+        let state = state.clone();
+        f.property::<bool, _>("CanGoPrevious", ())
+            .access(Access::Read)
+            .on_get(move |iter, _| {
+                iter.append(state.spotify_status().capabilities().go_previous);
+                Ok(())
+            })
+    };
 
     let property_shuffle = {
         let state = state.clone();
         let state2 = state.clone();
+        let conn = conn.clone();
         f.property::<bool, _>("Shuffle", ())
             .access(Access::ReadWrite)
+            .auto_emit_on_set(false)
             .on_get(move |iter, _| {
                 iter.append(get_shuffle(state.clone()));
                 Ok(())
             })
             .on_set(move |iter, _| {
-                if let Some(value) = iter.get() {
-                    state2.client().set_repeating(value);
-                }
+                let shuffle = iter.read::<bool>()?;
+
+                let backend = state2.backend();
+                backend.set_shuffling(shuffle).map_err(method_err)?;
+                let shuffle = confirm(
+                    backend,
+                    "shuffle",
+                    |backend| backend.is_shuffling(),
+                    |v| v == shuffle,
+                )
+                .map_err(method_err)?;
+
+                state2.spotify_status().set_shuffling(Some(shuffle));
+                emit_changed(&conn, "Shuffle", get_shuffle(state2.clone()));
                 Ok(())
             })
     };
@@ -498,7 +783,7 @@ fn run_server(state: Arc<AppState>, tx: Sender<MprisCommand>, rx: Receiver<Mpris
     let method_playpause = {
         let state = state.clone();
         f.method("PlayPause", (), move |m| {
-            state.client().play_pause();
+            state.backend().play_pause().map_err(method_err)?;
             Ok(vec![m.msg.method_return()])
         })
     };
@@ -506,7 +791,7 @@ fn run_server(state: Arc<AppState>, tx: Sender<MprisCommand>, rx: Receiver<Mpris
     let method_play = {
         let state = state.clone();
         f.method("Play", (), move |m| {
-            state.client().play();
+            state.backend().play().map_err(method_err)?;
             Ok(vec![m.msg.method_return()])
         })
     };
@@ -514,7 +799,7 @@ fn run_server(state: Arc<AppState>, tx: Sender<MprisCommand>, rx: Receiver<Mpris
     let method_pause = {
         let state = state.clone();
         f.method("Pause", (), move |m| {
-            state.client().pause();
+            state.backend().pause().map_err(method_err)?;
             Ok(vec![m.msg.method_return()])
         })
     };
@@ -522,7 +807,7 @@ fn run_server(state: Arc<AppState>, tx: Sender<MprisCommand>, rx: Receiver<Mpris
     let method_stop = {
         let state = state.clone();
         f.method("Stop", (), move |m| {
-            state.client().pause();
+            stop(&state).map_err(method_err)?;
             Ok(vec![m.msg.method_return()])
         })
     };
@@ -530,7 +815,7 @@ fn run_server(state: Arc<AppState>, tx: Sender<MprisCommand>, rx: Receiver<Mpris
     let method_next = {
         let state = state.clone();
         f.method("Next", (), move |m| {
-            state.client().next();
+            state.backend().next().map_err(method_err)?;
             Ok(vec![m.msg.method_return()])
         })
     };
@@ -538,11 +823,47 @@ fn run_server(state: Arc<AppState>, tx: Sender<MprisCommand>, rx: Receiver<Mpris
     let method_previous = {
         let state = state.clone();
         f.method("Previous", (), move |m| {
-            state.client().prev();
+            state.backend().prev().map_err(method_err)?;
+            Ok(vec![m.msg.method_return()])
+        })
+    };
+
+    let method_seek = {
+        let state = state.clone();
+        f.method("Seek", (), move |m| {
+            let offset: i64 = m.msg.read1()?;
+            seek(&state, offset).map_err(method_err)?;
             Ok(vec![m.msg.method_return()])
         })
+        .inarg::<i64, _>("Offset")
     };
 
+    let method_setposition = {
+        let state = state.clone();
+        f.method("SetPosition", (), move |m| {
+            let (trackid, position): (Path, i64) = m.msg.read2()?;
+            set_position(&state, &trackid, position).map_err(method_err)?;
+            Ok(vec![m.msg.method_return()])
+        })
+        .inarg::<Path, _>("TrackId")
+        .inarg::<i64, _>("Position")
+    };
+
+    let method_openuri = {
+        let state = state.clone();
+        f.method("OpenUri", (), move |m| {
+            let uri: &str = m.msg.read1()?;
+            let uri = SpotifyUri::parse(uri)
+                .filter(|uri| OPEN_KINDS.contains(&uri.kind))
+                .ok_or_else(|| MethodErr::invalid_arg(&uri))?;
+            state.backend().play_uri(&uri).map_err(method_err)?;
+            Ok(vec![m.msg.method_return()])
+        })
+        .inarg::<&str, _>("Uri")
+    };
+
+    let signal_seeked = f.signal("Seeked", ()).sarg::<i64, _>("Position");
+
     let interface_player = f
         .interface("org.mpris.MediaPlayer2.Player", ())
         .add_p(property_playbackstatus)
@@ -565,7 +886,11 @@ fn run_server(state: Arc<AppState>, tx: Sender<MprisCommand>, rx: Receiver<Mpris
         .add_m(method_pause)
         .add_m(method_stop)
         .add_m(method_next)
-        .add_m(method_previous);
+        .add_m(method_previous)
+        .add_m(method_seek)
+        .add_m(method_setposition)
+        .add_m(method_openuri)
+        .add_s(signal_seeked);
 
     let tree = f.tree(()).add(
         f.object_path("/org/mpris/MediaPlayer2", ())
@@ -574,46 +899,63 @@ fn run_server(state: Arc<AppState>, tx: Sender<MprisCommand>, rx: Receiver<Mpris
             .add(interface_player),
     );
 
-    tree.set_registered(&conn, true)
-        .expect("failed to register tree");
+    tree.set_registered(&conn, true)?;
 
     conn.add_handler(tree);
 
+    // The last capabilities clients were told about.
+    let mut capabilities = state.spotify_status().capabilities();
+
     loop {
         if let Some(m) = conn.incoming(200).next() {
             println!("Unhandled dbus message: {:?}", m);
         }
 
-        let mut update = false;
+        // The reply to Quit is already sent by now.
+        if exiting.get() {
+            break;
+        }
 
-        match rx.try_recv() {
-            Ok(cmd) => {
-                match cmd {
-                    MprisCommand::Ok => {}
-                    MprisCommand::Tick => match state.mpris().locker.lock() {
-                        Ok(mut guard) => {
-                            match tx.send(MprisCommand::Ok) {
-                                Ok(_) => {
-                                    update = state.spotify_status().has_changed();
-                                }
-                                Err(_) => {}
-                            };
-                            *guard = ();
+        let mut update = false;
+        // Held until the changes are sent, the poller resets them as soon as
+        // it gets the lock.
+        let mut _guard = None;
+
+        if let Ok(cmd) = rx.try_recv() {
+            match cmd {
+                MprisCommand::Ok => {}
+                MprisCommand::Tick => {
+                    if let Ok(guard) = state.mpris().locker.lock() {
+                        if tx.send(MprisCommand::Ok).is_ok() {
+                            update = state.spotify_status().has_changed();
                         }
-                        Err(_) => {}
-                    },
-                    MprisCommand::Stop => break,
-                };
-            }
-            _ => {}
+                        _guard = Some(guard);
+                    }
+                }
+                MprisCommand::Stop => break,
+            };
         }
 
         if update {
-            let mut changed: PropertiesPropertiesChanged = Default::default();
-            changed.interface_name = "org.mpris.MediaPlayer2.Player".to_string();
+            let mut changed = PropertiesPropertiesChanged {
+                interface_name: "org.mpris.MediaPlayer2.Player".to_string(),
+                ..Default::default()
+            };
 
             let status = state.spotify_status();
 
+            // Position is never part of PropertiesChanged, clients extrapolate
+            // it and only need to hear about jumps.
+            if status.seeked() {
+                let seeked = Message::signal(
+                    &Path::new("/org/mpris/MediaPlayer2".to_string()).unwrap(),
+                    &"org.mpris.MediaPlayer2.Player".into(),
+                    &"Seeked".into(),
+                )
+                .append1(get_position(state.clone()));
+                conn.send(seeked).unwrap();
+            }
+
             if status.track().has_changed() {
                 changed.changed_properties.insert(
                     "Metadata".to_string(),
@@ -621,6 +963,52 @@ fn run_server(state: Arc<AppState>, tx: Sender<MprisCommand>, rx: Receiver<Mpris
                 );
             }
 
+            if status.capabilities_changed() {
+                let previous = capabilities;
+                capabilities = status.capabilities();
+
+                for (name, before, after) in &[
+                    ("CanPlay", previous.play, capabilities.play),
+                    ("CanPause", previous.pause, capabilities.pause),
+                    ("CanSeek", previous.seek, capabilities.seek),
+                    ("CanGoNext", previous.go_next, capabilities.go_next),
+                    (
+                        "CanGoPrevious",
+                        previous.go_previous,
+                        capabilities.go_previous,
+                    ),
+                ] {
+                    if before != after {
+                        changed
+                            .changed_properties
+                            .insert(name.to_string(), Variant(Box::new(*after)));
+                    }
+                }
+
+                let mut root_changed = PropertiesPropertiesChanged {
+                    interface_name: "org.mpris.MediaPlayer2".to_string(),
+                    ..Default::default()
+                };
+
+                for (name, before, after) in &[
+                    ("CanQuit", previous.quit, capabilities.quit),
+                    ("CanRaise", previous.raise, capabilities.raise),
+                ] {
+                    if before != after {
+                        root_changed
+                            .changed_properties
+                            .insert(name.to_string(), Variant(Box::new(*after)));
+                    }
+                }
+
+                if !root_changed.changed_properties.is_empty() {
+                    conn.send(root_changed.to_emit_message(
+                        &Path::new("/org/mpris/MediaPlayer2".to_string()).unwrap(),
+                    ))
+                    .unwrap();
+                }
+            }
+
             changed.changed_properties.insert(
                 "PlaybackStatus".to_string(),
                 Variant(Box::new(get_playbackstatus(state.clone()))),
@@ -628,7 +1016,7 @@ fn run_server(state: Arc<AppState>, tx: Sender<MprisCommand>, rx: Receiver<Mpris
 
             changed.changed_properties.insert(
                 "LoopStatus".to_string(),
-                Variant(Box::new(get_playbackstatus(state.clone()))),
+                Variant(Box::new(get_loopstatus(state.clone()))),
             );
 
             changed.changed_properties.insert(
@@ -647,21 +1035,690 @@ fn run_server(state: Arc<AppState>, tx: Sender<MprisCommand>, rx: Receiver<Mpris
                 )),
             );
 
-            changed.changed_properties.insert(
-                "Position".to_string(),
-                Variant(Box::new(
-                    state
-                        .spotify_status()
-                        .position()
-                        .map(|v| (v * 1_000_000.0).round() as i64)
-                        .unwrap_or_default(),
-                )),
+            if status.properties_changed() {
+                conn.send(
+                    changed.to_emit_message(
+                        &Path::new("/org/mpris/MediaPlayer2".to_string()).unwrap(),
+                    ),
+                )
+                .unwrap();
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::backend::{CommandBackend, MockBackend};
+    use crate::config::bus_name;
+    use crate::rules::MetadataRules;
+    use dbus::arg::Get;
+    use dbus::stdintf::org_freedesktop_dbus::Properties;
+    use std::io::{BufRead, BufReader};
+    use std::process::{Child, Command, Stdio};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::OnceLock;
+
+    const PATH: &str = "/org/mpris/MediaPlayer2";
+    const ROOT: &str = "org.mpris.MediaPlayer2";
+    const PLAYER: &str = "org.mpris.MediaPlayer2.Player";
+
+    // One private bus for all tests. The shell kills the daemon once the
+    // tests exit and its stdin closes.
+    pub(crate) fn bus_address() -> String {
+        static BUS: OnceLock<(Child, String)> = OnceLock::new();
+
+        BUS.get_or_init(|| {
+            let mut daemon = Command::new("sh")
+                .arg("-c")
+                .arg(
+                    "command -v dbus-daemon >/dev/null || exit; \
+                     dbus-daemon --session --nofork --print-address=1 & read _; kill $!",
+                )
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .unwrap();
+
+            let mut address = String::new();
+            BufReader::new(daemon.stdout.take().unwrap())
+                .read_line(&mut address)
+                .unwrap();
+
+            let address = address.trim().to_string();
+            assert!(
+                !address.is_empty(),
+                "the D-Bus tests need dbus-daemon on the PATH"
             );
+            (daemon, address)
+        })
+        .1
+        .clone()
+    }
+
+    // A bridge to a mock backend, exported under a name of its own.
+    pub(crate) struct Bridge {
+        pub state: Arc<AppState<MockBackend>>,
+        pub conn: Connection,
+        pub name: String,
+    }
+
+    impl Bridge {
+        pub fn start() -> Bridge {
+            static NEXT: AtomicUsize = AtomicUsize::new(0);
 
-            conn.send(
-                changed.to_emit_message(&Path::new("/org/mpris/MediaPlayer2".to_string()).unwrap()),
-            )
+            Bridge::named(format!("test{}", NEXT.fetch_add(1, Ordering::Relaxed)))
+        }
+
+        pub fn named(name: String) -> Bridge {
+            let address = bus_address();
+            let state = Arc::new(AppState::new(
+                MockBackend::new(),
+                None,
+                MetadataRules::default(),
+            ));
+            state.mpris().run(
+                state.clone(),
+                name.clone(),
+                BusKind::Address(address.clone()),
+                QuitAction::Stay,
+            );
+
+            {
+                let state = state.clone();
+                spawn(move || loop {
+                    let _ = state.update();
+                    sleep(Duration::from_millis(20));
+                });
+            }
+
+            let bridge = Bridge {
+                state,
+                conn: BusKind::Address(address).connect().unwrap(),
+                name: bus_name(&name),
+            };
+            bridge
+                .conn
+                .add_match(&PropertiesPropertiesChanged::match_str(
+                    Some(&bridge.name.as_str().into()),
+                    Some(&PATH.into()),
+                ))
+                .unwrap();
+            bridge
+                .conn
+                .add_match(&format!(
+                    "type='signal',sender='{}',path='{}',interface='{}',member='Seeked'",
+                    bridge.name, PATH, PLAYER
+                ))
+                .unwrap();
+            bridge.wait_for(|| bridge.try_get::<bool>(PLAYER, "CanPlay") == Some(true));
+            bridge
+        }
+
+        pub fn try_get<T: for<'b> Get<'b>>(&self, interface: &str, property: &str) -> Option<T> {
+            self.conn
+                .with_path(self.name.as_str(), PATH, 1_000)
+                .get(interface, property)
+                .ok()
+        }
+
+        pub fn get<T: for<'b> Get<'b>>(&self, interface: &str, property: &str) -> T {
+            self.try_get(interface, property)
+                .unwrap_or_else(|| panic!("cannot read {}", property))
+        }
+
+        pub fn set<T: dbus::arg::Arg + dbus::arg::Append>(
+            &self,
+            property: &str,
+            value: T,
+        ) -> std::result::Result<(), dbus::Error> {
+            self.conn
+                .with_path(self.name.as_str(), PATH, 5_000)
+                .set(PLAYER, property, value)
+        }
+
+        pub fn call(
+            &self,
+            interface: &str,
+            method: &str,
+        ) -> std::result::Result<Message, dbus::Error> {
+            self.call_with(interface, method, |msg| msg)
+        }
+
+        // `args` appends the arguments to the call.
+        pub fn call_with<F: FnOnce(Message) -> Message>(
+            &self,
+            interface: &str,
+            method: &str,
+            args: F,
+        ) -> std::result::Result<Message, dbus::Error> {
+            let msg =
+                Message::new_method_call(self.name.as_str(), PATH, interface, method).unwrap();
+            self.conn.send_with_reply_and_block(args(msg), 5_000)
+        }
+
+        // The first value of `property` in a PropertiesChanged signal that
+        // `accept` takes.
+        pub fn changed<F: Fn(&dyn RefArg) -> bool>(
+            &self,
+            property: &str,
+            accept: F,
+        ) -> Box<dyn RefArg> {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while Instant::now() < deadline {
+                for msg in self.conn.incoming(250) {
+                    let mut changed = match PropertiesPropertiesChanged::from_message(&msg) {
+                        Some(changed) => changed,
+                        None => continue,
+                    };
+                    if let Some(Variant(value)) = changed.changed_properties.remove(property) {
+                        if accept(&*value) {
+                            return value;
+                        }
+                    }
+                }
+            }
+            panic!("{} was not emitted", property);
+        }
+
+        // The position of the next Seeked signal, if one comes within `timeout`.
+        pub fn seeked(&self, timeout: Duration) -> Option<i64> {
+            let deadline = Instant::now() + timeout;
+            while Instant::now() < deadline {
+                for msg in self.conn.incoming(250) {
+                    if msg.member().is_some_and(|member| &*member == "Seeked") {
+                        return msg.get1();
+                    }
+                }
+            }
+            None
+        }
+
+        pub fn execute(&self, line: &str) {
+            self.state.backend().execute(line).unwrap();
+        }
+
+        // Checks less often than the server loop times out, calls arriving
+        // faster would keep it from handling polls.
+        pub fn wait_for<F: Fn() -> bool>(&self, done: F) {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while !done() {
+                assert!(Instant::now() < deadline, "timed out");
+                sleep(Duration::from_millis(250));
+            }
+        }
+    }
+
+    #[test]
+    fn raise_launches_the_player() {
+        let bridge = Bridge::start();
+        assert!(bridge.get::<bool>(ROOT, "CanRaise"));
+
+        bridge.execute("quit");
+        bridge.wait_for(|| !bridge.get::<bool>(PLAYER, "CanPlay"));
+        assert!(bridge.get::<bool>(ROOT, "CanRaise"));
+
+        bridge.call(ROOT, "Raise").unwrap();
+        assert!(bridge.state.backend().state().is_ok());
+        bridge.wait_for(|| bridge.get::<bool>(PLAYER, "CanPlay"));
+    }
+
+    #[test]
+    fn emitted_loop_status_matches_the_getter() {
+        let bridge = Bridge::start();
+
+        for (command, status) in &[
+            ("repeat track", "Track"),
+            ("repeat on", "Playlist"),
+            ("repeat off", "None"),
+        ] {
+            bridge.execute(command);
+            bridge.changed("LoopStatus", |value| value.as_str() == Some(status));
+            assert_eq!(bridge.get::<String>(PLAYER, "LoopStatus"), *status);
+        }
+
+        for status in &["Playlist", "Track", "None"] {
+            bridge.set("LoopStatus", status.to_string()).unwrap();
+            bridge.changed("LoopStatus", |value| value.as_str() == Some(status));
+            assert_eq!(bridge.get::<String>(PLAYER, "LoopStatus"), *status);
+        }
+    }
+
+    #[test]
+    fn setting_shuffle_reaches_the_player() {
+        let bridge = Bridge::start();
+
+        for shuffle in &[true, false] {
+            bridge.set("Shuffle", *shuffle).unwrap();
+            assert_eq!(
+                bridge.state.backend().is_shuffling().unwrap(),
+                Some(*shuffle)
+            );
+            assert_eq!(bridge.get::<bool>(PLAYER, "Shuffle"), *shuffle);
+            bridge.changed("Shuffle", |value| value.as_i64() == Some(*shuffle as i64));
+        }
+    }
+
+    #[test]
+    fn setting_loop_status_reaches_the_player() {
+        let bridge = Bridge::start();
+
+        for (status, mode) in &[
+            ("Track", RepeatMode::Track),
+            ("Playlist", RepeatMode::Context),
+            ("None", RepeatMode::Off),
+        ] {
+            bridge.set("LoopStatus", status.to_string()).unwrap();
+            assert_eq!(bridge.state.backend().repeat_mode().unwrap(), Some(*mode));
+            assert_eq!(bridge.get::<String>(PLAYER, "LoopStatus"), *status);
+        }
+
+        assert!(bridge.set("LoopStatus", "Album".to_string()).is_err());
+        assert_eq!(bridge.get::<String>(PLAYER, "LoopStatus"), "None");
+    }
+
+    #[test]
+    fn setting_volume_reaches_the_player() {
+        let bridge = Bridge::start();
+
+        for (volume, expected) in &[(0.42, 42), (1.5, 100), (-1.0, 0)] {
+            bridge.set("Volume", *volume).unwrap();
+            assert_eq!(bridge.state.backend().volume().unwrap(), Some(*expected));
+            assert_eq!(
+                bridge.get::<f64>(PLAYER, "Volume"),
+                *expected as f64 / 100.0
+            );
+        }
+    }
+
+    // A player that applies changes late, behind a backend that keeps its
+    // last status until told otherwise.
+    #[test]
+    fn confirm_reads_past_the_command_cache() {
+        let dir = std::env::temp_dir().join(format!("bridge-confirm-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let status = dir.join("status");
+        std::fs::write(&status, "status=playing\nvolume=10\n").unwrap();
+
+        let commands = dir.join("commands");
+        std::fs::write(
+            &commands,
+            format!(
+                "status = cat {0}\n\
+                 set_volume = (sleep 0.2; printf 'status=playing\\nvolume={{}}\\n' > {0}) \
+                 >/dev/null 2>&1 &\n",
+                status.display()
+            ),
+        )
+        .unwrap();
+
+        let backend = CommandBackend::open(&commands).unwrap();
+        assert_eq!(backend.volume().unwrap(), Some(10));
+        backend.set_volume(50).unwrap();
+        let volume = confirm(&backend, "volume", |backend| backend.volume(), |v| v == 50);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(volume.unwrap(), 50);
+    }
+
+    // A player that takes the command and keeps its volume.
+    #[test]
+    fn confirm_gives_up_on_an_ignored_write() {
+        let commands = std::env::temp_dir().join(format!("bridge-ignored-{}", std::process::id()));
+        std::fs::write(
+            &commands,
+            "status = printf 'status=playing\\nvolume=10\\n'\n\
+             set_volume = true\n",
+        )
+        .unwrap();
+
+        let backend = CommandBackend::open(&commands).unwrap();
+        backend.set_volume(50).unwrap();
+        let start = Instant::now();
+        let volume = confirm(&backend, "volume", |backend| backend.volume(), |v| v == 50);
+        std::fs::remove_file(&commands).unwrap();
+
+        assert!(start.elapsed() >= CONFIRM_TIMEOUT);
+        let err = volume.unwrap_err().to_string();
+        assert!(err.contains("kept its volume at 10"), "{}", err);
+    }
+
+    fn signatures<'a, I: Iterator<Item = (&'a str, &'a dyn RefArg)>>(
+        entries: I,
+    ) -> HashMap<String, String> {
+        entries
+            .map(|(key, value)| (key.to_string(), value.signature().to_string()))
+            .collect()
+    }
+
+    // Dictionaries decoded from a signal iterate as keys followed by their
+    // variants.
+    fn entries(metadata: &dyn RefArg) -> Vec<(&str, &dyn RefArg)> {
+        let mut items = metadata.as_iter().unwrap();
+        let mut entries = Vec::new();
+        while let (Some(key), Some(value)) = (items.next(), items.next()) {
+            entries.push((
+                key.as_str().unwrap(),
+                value.as_iter().unwrap().next().unwrap(),
+            ));
+        }
+        entries
+    }
+
+    fn assert_strict(types: &HashMap<String, String>) {
+        assert_eq!(types["mpris:trackid"], "o");
+        assert_eq!(types["mpris:length"], "x");
+        assert_eq!(types["xesam:artist"], "as");
+        assert_eq!(types["xesam:albumArtist"], "as");
+        assert_eq!(types["xesam:title"], "s");
+    }
+
+    #[test]
+    fn metadata_has_strict_types() {
+        let bridge = Bridge::start();
+
+        let metadata = bridge.get::<HashMap<String, Variant<Box<dyn RefArg>>>>(PLAYER, "Metadata");
+        assert_strict(&signatures(
+            metadata
+                .iter()
+                .map(|(key, value)| (key.as_str(), &*value.0)),
+        ));
+
+        bridge.execute("next");
+        let metadata = bridge.changed("Metadata", |metadata| {
+            entries(metadata)
+                .iter()
+                .any(|(key, value)| *key == "xesam:title" && value.as_str() == Some("Take On Me"))
+        });
+        assert_strict(&signatures(entries(&*metadata).into_iter()));
+    }
+
+    // Metadata is cached by id, but some of it changes while the id stays.
+    #[test]
+    fn metadata_follows_starring_on_the_same_track() {
+        let bridge = Bridge::start();
+
+        let metadata =
+            || bridge.get::<HashMap<String, Variant<Box<dyn RefArg>>>>(PLAYER, "Metadata");
+        let track = "id=spotify:track:0000000000000000000023;name=Song";
+
+        for (starred, count) in &[(false, 1), (true, 2)] {
+            bridge.execute("clear");
+            bridge.execute(&format!(
+                "track {};starred={};played_count={}",
+                track, starred, count
+            ));
+            bridge.execute("play");
+            bridge.wait_for(|| {
+                let metadata = metadata();
+                metadata
+                    .get("spotifybridge:starred")
+                    .and_then(|value| value.0.as_i64())
+                    == Some(*starred as i64)
+                    && metadata
+                        .get("xesam:useCount")
+                        .and_then(|value| value.0.as_i64())
+                        == Some(*count)
+            });
+        }
+    }
+
+    // Players that list artists one by one are not split again.
+    #[test]
+    fn artist_lists_pass_through() {
+        let bridge = Bridge::start();
+
+        bridge.execute("clear");
+        bridge.execute(
+            "track id=spotify:track:0000000000000000000024;name=Song;\
+             artist=Earth, Wind, Fire;artists=Earth, Wind;artists=Fire",
+        );
+        bridge.execute("play");
+        bridge.wait_for(|| {
+            bridge
+                .get::<HashMap<String, Variant<Box<dyn RefArg>>>>(PLAYER, "Metadata")
+                .get("xesam:title")
+                .and_then(|value| value.0.as_str().map(String::from))
+                == Some("Song".to_string())
+        });
+
+        let metadata = bridge.get::<HashMap<String, Variant<Box<dyn RefArg>>>>(PLAYER, "Metadata");
+        let artists = metadata["xesam:artist"]
+            .0
+            .as_iter()
+            .unwrap()
+            .filter_map(|artist| artist.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(artists, vec!["Earth, Wind", "Fire"]);
+    }
+
+    #[test]
+    fn identity_leaves_out_the_prefix() {
+        let bridge = Bridge::start();
+        assert_eq!(bridge.get::<String>(ROOT, "Identity"), &bridge.name[23..]);
+
+        let bridge = Bridge::named("org.mpris.MediaPlayer2.prefixed".to_string());
+        assert_eq!(bridge.name, "org.mpris.MediaPlayer2.prefixed");
+        assert_eq!(bridge.get::<String>(ROOT, "Identity"), "prefixed");
+    }
+
+    #[test]
+    fn quit_stops_the_player_and_keeps_the_bridge() {
+        let bridge = Bridge::start();
+        assert!(bridge.get::<bool>(ROOT, "CanQuit"));
+
+        bridge.call(ROOT, "Quit").unwrap();
+        assert!(matches!(
+            bridge.state.backend().state(),
+            Err(BridgeError::NotRunning)
+        ));
+
+        // The status follows at once, without waiting for a poll.
+        assert_eq!(bridge.get::<String>(PLAYER, "PlaybackStatus"), "Stopped");
+        assert!(!bridge.get::<bool>(PLAYER, "CanPlay"));
+        assert!(!bridge.get::<bool>(ROOT, "CanQuit"));
+        let metadata = bridge.get::<HashMap<String, Variant<Box<dyn RefArg>>>>(PLAYER, "Metadata");
+        assert_eq!(metadata.len(), 1);
+        assert_eq!(
+            metadata["mpris:trackid"].0.as_str(),
+            Some("/org/mpris/MediaPlayer2/TrackList/NoTrack")
+        );
+    }
+
+    #[test]
+    fn seek_and_set_position_move_within_the_track() {
+        let bridge = Bridge::start();
+        let position = || bridge.state.backend().position().unwrap();
+        let set_position = |trackid: &str, position: i64| {
+            bridge
+                .call_with(PLAYER, "SetPosition", |msg| {
+                    msg.append2(Path::new(trackid).unwrap(), position)
+                })
+                .unwrap();
+        };
+        let seek = |offset: i64| {
+            bridge
+                .call_with(PLAYER, "Seek", |msg| msg.append1(offset))
+                .unwrap();
+        };
+        let current = "/com/spotify/track/4uLU6hMCjMI75M1A2tKUQC";
+
+        set_position(current, 60_000_000);
+        assert_eq!(position(), Some(60.0));
+
+        // Stale track ids and positions outside of the track are ignored.
+        set_position("/com/spotify/track/2WfaOiMkCvy7F5fcp2zZ8L", 10_000_000);
+        set_position(current, 214_000_000);
+        set_position(current, -1);
+        assert_eq!(position(), Some(60.0));
+
+        // Seeking starts from the position the bridge knows.
+        bridge.wait_for(|| bridge.get::<i64>(PLAYER, "Position") == 60_000_000);
+        seek(10_000_000);
+        assert_eq!(position(), Some(70.0));
+
+        bridge.wait_for(|| bridge.get::<i64>(PLAYER, "Position") == 70_000_000);
+        seek(-100_000_000);
+        assert_eq!(position(), Some(0.0));
+
+        bridge.wait_for(|| bridge.get::<i64>(PLAYER, "Position") == 0);
+        seek(300_000_000);
+        let track = bridge.state.backend().snapshot().unwrap().track;
+        assert_eq!(track.name.as_deref(), Some("Take On Me"));
+    }
+
+    #[test]
+    fn jumps_are_signalled_as_seeked() {
+        let bridge = Bridge::start();
+        let title = || {
+            let metadata =
+                bridge.get::<HashMap<String, Variant<Box<dyn RefArg>>>>(PLAYER, "Metadata");
+            metadata
+                .get("xesam:title")
+                .and_then(|title| title.0.as_str().map(String::from))
+        };
+
+        bridge.execute("seek 60");
+        assert_eq!(bridge.seeked(Duration::from_secs(5)), Some(60_000_000));
+
+        // Tracks without an id are told apart by their name.
+        bridge.execute("clear");
+        bridge.execute("track name=First;duration=100000");
+        bridge.execute("track name=Second;duration=100000");
+        bridge.execute("play");
+        bridge.execute("pause");
+        bridge.wait_for(|| title().as_deref() == Some("First"));
+        bridge.execute("seek 50");
+        assert_eq!(bridge.seeked(Duration::from_secs(5)), Some(50_000_000));
+
+        bridge.execute("next");
+        bridge.wait_for(|| title().as_deref() == Some("Second"));
+        assert_eq!(bridge.seeked(Duration::from_secs(1)), None);
+    }
+
+    #[test]
+    fn stop_rewinds_and_reports_stopped_until_played() {
+        let bridge = Bridge::start();
+        bridge.execute("seek 60");
+        bridge.execute("play");
+        bridge.wait_for(|| bridge.get::<String>(PLAYER, "PlaybackStatus") == "Playing");
+
+        bridge.call(PLAYER, "Stop").unwrap();
+        let backend = bridge.state.backend();
+        assert_eq!(backend.state().unwrap(), Some(PlaybackStatus::Paused));
+        assert_eq!(backend.position().unwrap(), Some(0.0));
+        assert_eq!(bridge.get::<String>(PLAYER, "PlaybackStatus"), "Stopped");
+
+        // Polls find the player paused, which stays a stop.
+        sleep(Duration::from_millis(300));
+        assert_eq!(bridge.get::<String>(PLAYER, "PlaybackStatus"), "Stopped");
+
+        bridge.call(PLAYER, "Play").unwrap();
+        bridge.wait_for(|| bridge.get::<String>(PLAYER, "PlaybackStatus") == "Playing");
+    }
+
+    #[test]
+    fn failed_calls_reply_with_named_errors() {
+        let bridge = Bridge::start();
+        let error = |method| {
+            let err = bridge.call(PLAYER, method).unwrap_err();
+            err.name().unwrap_or_default().to_string()
+        };
+
+        for (failure, name) in &[
+            ("timeout", "org.mpris.MediaPlayer2.Error.Timeout"),
+            ("unsupported", "org.mpris.MediaPlayer2.Error.NotSupported"),
+            (
+                "permission",
+                "org.mpris.MediaPlayer2.Error.PermissionDenied",
+            ),
+        ] {
+            bridge.execute(&format!("fail {}", failure));
+            assert_eq!(error("Play"), *name);
+        }
+
+        bridge.execute("fail off");
+        bridge.call(PLAYER, "Play").unwrap();
+
+        bridge.execute("quit");
+        assert_eq!(error("Pause"), "org.mpris.MediaPlayer2.Error.NotRunning");
+    }
+
+    #[test]
+    fn open_uri_plays_links() {
+        let bridge = Bridge::start();
+        let schemes = bridge.get::<Vec<String>>(ROOT, "SupportedUriSchemes");
+        assert!(schemes.iter().any(|scheme| scheme == "https"));
+
+        bridge
+            .call_with(PLAYER, "OpenUri", |msg| {
+                msg.append1("https://open.spotify.com/track/7GhIk7Il098yCjg4BQjzvb?si=abc")
+            })
             .unwrap();
+        let snapshot = bridge.state.backend().snapshot().unwrap();
+        assert_eq!(snapshot.playback_status, PlaybackStatus::Playing);
+        assert_eq!(
+            snapshot.track.id.as_deref(),
+            Some("spotify:track:7GhIk7Il098yCjg4BQjzvb")
+        );
+    }
+
+    #[test]
+    fn open_uri_rejects_what_it_cannot_play() {
+        let bridge = Bridge::start();
+
+        for uri in &[
+            "spotify:artist:0gxyHStUsqpMadRV0Di1Qt",
+            "https://example.com/track/7GhIk7Il098yCjg4BQjzvb",
+            "garbage",
+        ] {
+            let err = bridge
+                .call_with(PLAYER, "OpenUri", |msg| msg.append1(*uri))
+                .unwrap_err();
+            assert_eq!(err.name(), Some("org.freedesktop.DBus.Error.InvalidArgs"));
         }
+
+        let snapshot = bridge.state.backend().snapshot().unwrap();
+        assert_eq!(snapshot.playback_status, PlaybackStatus::Paused);
+    }
+
+    #[test]
+    fn tracks_without_an_id_get_a_path_of_their_own() {
+        let bridge = Bridge::start();
+        let metadata =
+            || bridge.get::<HashMap<String, Variant<Box<dyn RefArg>>>>(PLAYER, "Metadata");
+        let trackid = || {
+            metadata()["mpris:trackid"]
+                .0
+                .as_str()
+                .map(String::from)
+                .unwrap()
+        };
+
+        // Nothing but NoTrack without a track.
+        bridge.execute("clear");
+        bridge.wait_for(|| trackid() == "/org/mpris/MediaPlayer2/TrackList/NoTrack");
+        assert_eq!(metadata().len(), 1);
+
+        bridge.execute("track name=Take On Me (Live);duration=225280");
+        bridge.execute("play");
+        bridge.execute("pause");
+        let path = "/com/spotify/title/Take_On_Me__Live__225280";
+        bridge.wait_for(|| trackid() == path);
+        assert_eq!(
+            metadata()["xesam:title"].0.as_str(),
+            Some("Take On Me (Live)")
+        );
+
+        bridge
+            .call_with(PLAYER, "SetPosition", |msg| {
+                msg.append2(Path::new(path).unwrap(), 60_000_000i64)
+            })
+            .unwrap();
+        assert_eq!(bridge.state.backend().position().unwrap(), Some(60.0));
+        assert_eq!(trackid(), path);
     }
 }
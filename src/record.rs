@@ -0,0 +1,53 @@
+use crate::snapshot::StatusSnapshot;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Error, ErrorKind, LineWriter, Result, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+pub struct Recorder {
+    file: Mutex<LineWriter<File>>,
+    start: Instant,
+}
+
+impl Recorder {
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Recorder> {
+        Ok(Recorder {
+            file: Mutex::new(LineWriter::new(File::create(path)?)),
+            start: Instant::now(),
+        })
+    }
+
+    pub fn record(&self, snapshot: &StatusSnapshot) -> Result<()> {
+        let time = self.start.elapsed().as_millis();
+        let mut file = self.file.lock().unwrap();
+        writeln!(file, "time={};{}", time, snapshot.encode())
+    }
+}
+
+pub fn read_recording<P: AsRef<Path>>(path: P) -> Result<Vec<(Duration, StatusSnapshot)>> {
+    let mut frames = Vec::new();
+
+    for (n, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let err =
+            |msg: String| Error::new(ErrorKind::InvalidData, format!("line {}: {}", n + 1, msg));
+
+        let mut parts = line.splitn(2, ';');
+        let time = parts
+            .next()
+            .and_then(|v| v.strip_prefix("time="))
+            .and_then(|v| v.parse::<u64>().ok())
+            .ok_or_else(|| err("missing timestamp".to_string()))?;
+        let snapshot = StatusSnapshot::decode(parts.next().unwrap_or_default())
+            .map_err(|e| err(e.to_string()))?;
+
+        frames.push((Duration::from_millis(time), snapshot));
+    }
+
+    Ok(frames)
+}
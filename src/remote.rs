@@ -0,0 +1,319 @@
+use crate::backend::PlayerBackend;
+use crate::error::{self, BridgeError};
+use crate::status::RepeatMode;
+use crate::AppState;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::{Arc, Mutex};
+use std::thread::spawn;
+use std::time::Duration;
+
+// A bridge that stops reading is dropped after this long, instead of holding
+// up the others.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Addresses are either `host:port` or, on unix, `unix:/path/to/socket`.
+pub enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Stream {
+    pub fn connect(addr: &str) -> io::Result<Stream> {
+        #[cfg(unix)]
+        {
+            if let Some(path) = addr.strip_prefix("unix:") {
+                return Ok(Stream::Unix(UnixStream::connect(path)?));
+            }
+        }
+
+        Ok(Stream::Tcp(TcpStream::connect(addr)?))
+    }
+
+    pub fn try_clone(&self) -> io::Result<Stream> {
+        match self {
+            Stream::Tcp(stream) => Ok(Stream::Tcp(stream.try_clone()?)),
+            #[cfg(unix)]
+            Stream::Unix(stream) => Ok(Stream::Unix(stream.try_clone()?)),
+        }
+    }
+
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_write_timeout(timeout),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.set_write_timeout(timeout),
+        }
+    }
+
+    pub fn shutdown(&self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.shutdown(Shutdown::Both),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.shutdown(Shutdown::Both),
+        }
+    }
+
+    pub fn send_line(&mut self, line: &str) -> io::Result<()> {
+        let mut buf = String::with_capacity(line.len() + 1);
+        buf.push_str(line);
+        buf.push('\n');
+        self.write_all(buf.as_bytes())?;
+        self.flush()
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl Listener {
+    fn bind(addr: &str) -> io::Result<Listener> {
+        #[cfg(unix)]
+        {
+            if let Some(path) = addr.strip_prefix("unix:") {
+                return match UnixListener::bind(path) {
+                    // A socket left by a run that did not exit cleanly has no
+                    // one listening on it, anything else is not ours to remove.
+                    Err(err) if err.kind() == io::ErrorKind::AddrInUse && is_stale(path) => {
+                        std::fs::remove_file(path)?;
+                        Ok(Listener::Unix(UnixListener::bind(path)?))
+                    }
+                    res => Ok(Listener::Unix(res?)),
+                };
+            }
+        }
+
+        Ok(Listener::Tcp(TcpListener::bind(addr)?))
+    }
+
+    fn accept(&self) -> io::Result<Stream> {
+        match self {
+            Listener::Tcp(listener) => Ok(Stream::Tcp(listener.accept()?.0)),
+            #[cfg(unix)]
+            Listener::Unix(listener) => Ok(Stream::Unix(listener.accept()?.0)),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn is_stale(path: &str) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+
+    let socket = std::fs::symlink_metadata(path)
+        .map(|metadata| metadata.file_type().is_socket())
+        .unwrap_or(false);
+    socket && UnixStream::connect(path).is_err()
+}
+
+fn execute<B: PlayerBackend>(backend: &B, line: &str) -> error::Result<()> {
+    let invalid = || {
+        BridgeError::Backend(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("bad command `{}`", line),
+        ))
+    };
+
+    let mut parts = line.splitn(2, ' ');
+    let command = parts.next().unwrap_or_default();
+    let arg = parts.next().unwrap_or_default();
+
+    match command {
+        "play_pause" => backend.play_pause(),
+        "play" => backend.play(),
+        "pause" => backend.pause(),
+        "next" => backend.next(),
+        "prev" => backend.prev(),
+        "raise" => backend.raise(),
+        "quit" => backend.quit(),
+        "play_uri" => backend.play_uri(&arg.parse().map_err(|_| invalid())?),
+        "set_shuffling" => backend.set_shuffling(arg.parse().map_err(|_| invalid())?),
+        "set_repeat_mode" => backend.set_repeat_mode(arg.parse().map_err(|_| invalid())?),
+        // Sent by bridges from before track repeat.
+        "set_repeating" => backend.set_repeat_mode(RepeatMode::from(
+            arg.parse::<bool>().map_err(|_| invalid())?,
+        )),
+        "set_position" => backend.set_position(arg.parse().map_err(|_| invalid())?),
+        "set_volume" => backend.set_volume(arg.parse().map_err(|_| invalid())?),
+        _ => Err(invalid()),
+    }
+}
+
+type Clients = Arc<Mutex<Vec<Arc<Mutex<Stream>>>>>;
+
+fn handle_client<B: PlayerBackend>(state: Arc<AppState<B>>, stream: Stream, clients: Clients) {
+    if stream.set_write_timeout(Some(WRITE_TIMEOUT)).is_err() {
+        return;
+    }
+    let writer = match stream.try_clone() {
+        Ok(writer) => Arc::new(Mutex::new(writer)),
+        Err(_) => return,
+    };
+
+    let hello = [
+        format!("capabilities {}", state.backend().capabilities().encode()),
+        format!("status {}", state.spotify_status().snapshot().encode()),
+    ];
+    if hello
+        .iter()
+        .any(|line| writer.lock().unwrap().send_line(line).is_err())
+    {
+        return;
+    }
+    clients.lock().unwrap().push(writer.clone());
+
+    for line in BufReader::new(stream).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+
+        let reply = match execute(state.backend(), line.trim()) {
+            Ok(()) => "ok".to_string(),
+            Err(err) => format!("error {} {}", err.dbus_name(), err).replace('\n', " "),
+        };
+
+        if writer.lock().unwrap().send_line(&reply).is_err() {
+            break;
+        }
+    }
+
+    clients
+        .lock()
+        .unwrap()
+        .retain(|client| !Arc::ptr_eq(client, &writer));
+}
+
+// Polls the backend and pushes every status to the connected bridges, which
+// send back the control commands issued by their MPRIS clients.
+pub fn serve<B: PlayerBackend>(state: Arc<AppState<B>>, addr: &str) -> io::Result<()> {
+    let listener = Listener::bind(addr)?;
+    let clients: Clients = Arc::new(Mutex::new(Vec::new()));
+
+    {
+        let state = state.clone();
+        let clients = clients.clone();
+        spawn(move || loop {
+            match listener.accept() {
+                Ok(stream) => {
+                    let state = state.clone();
+                    let clients = clients.clone();
+                    spawn(move || handle_client(state, stream, clients));
+                }
+                Err(err) => println!("{}", err),
+            }
+        });
+    }
+
+    let mut capabilities = state.backend().capabilities();
+    let mut last_error = None;
+
+    loop {
+        // Errors usually last for many polls, only report when they change.
+        let error = state.update().err().map(|err| err.to_string());
+        if error != last_error {
+            if let Some(ref error) = error {
+                println!("{}", error);
+            }
+            last_error = error;
+        }
+
+        let mut lines = Vec::new();
+        if state.backend().capabilities() != capabilities {
+            capabilities = state.backend().capabilities();
+            lines.push(format!("capabilities {}", capabilities.encode()));
+        }
+        lines.push(format!(
+            "status {}",
+            state.spotify_status().snapshot().encode()
+        ));
+
+        // Written without holding the list, so clients can come and go.
+        let current = clients.lock().unwrap().clone();
+        let failed = current
+            .into_iter()
+            .filter(|client| {
+                let mut client = client.lock().unwrap();
+                let sent = lines.iter().all(|line| client.send_line(line).is_ok());
+                if !sent {
+                    // Ends the client's reader as well.
+                    let _ = client.shutdown();
+                }
+                !sent
+            })
+            .collect::<Vec<_>>();
+        clients
+            .lock()
+            .unwrap()
+            .retain(|client| !failed.iter().any(|f| Arc::ptr_eq(client, f)));
+        state.reset();
+
+        state.backend().wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("bridge-{}-{}", name, std::process::id()))
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn binding_keeps_what_is_not_a_stale_socket() {
+        let file = path("file");
+        std::fs::write(&file, "keep me").unwrap();
+        let addr = format!("unix:{}", file.display());
+        let err = Listener::bind(&addr).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "keep me");
+        std::fs::remove_file(&file).unwrap();
+
+        let socket = path("socket");
+        let addr = format!("unix:{}", socket.display());
+        let live = Listener::bind(&addr).unwrap();
+        let err = Listener::bind(&addr).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+
+        // Dropping a listener leaves its socket behind.
+        drop(live);
+        assert!(socket.exists());
+        Listener::bind(&addr).unwrap();
+        std::fs::remove_file(&socket).unwrap();
+    }
+}
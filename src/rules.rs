@@ -0,0 +1,315 @@
+use regex::{Captures, Regex};
+use std::io::{self, Error, ErrorKind};
+use std::path::Path;
+
+const SEPARATORS: &[&str] = &[",", "&", "feat.", "ft.", "x"];
+
+// Names that contain a separator but are a single artist.
+const EXCEPTIONS: &[&str] = &[
+    "Crosby, Stills, Nash & Young",
+    "Earth, Wind & Fire",
+    "Hall & Oates",
+    "Lil Nas X",
+    "Mumford & Sons",
+    "Simon & Garfunkel",
+    "Tyler, The Creator",
+    "Years & Years",
+];
+
+// Used with `clean_titles = on`. Featured artists move to the artists.
+const FEATURES: &[&str] = &[
+    r"(?i)\(feat\. ([^()\[\]]+)\)",
+    r"(?i)\[feat\. ([^()\[\]]+)\]",
+    r"(?i)\(ft\. ([^()\[\]]+)\)",
+    r"(?i)\[ft\. ([^()\[\]]+)\]",
+    r"(?i)\(featuring ([^()\[\]]+)\)",
+    r"(?i)\(with ([^()\[\]]+)\)",
+];
+
+const REPLACEMENTS: &[(&str, &str)] = &[
+    (r"(?i)- [^-()\[\]]*remaster[^-()\[\]]*$", ""),
+    (r"(?i)\([^()\[\]]*remaster[^()\[\]]*\)", ""),
+    (r"(?i)\[[^()\[\]]*remaster[^()\[\]]*\]", ""),
+    (r"(?i)- radio edit$", ""),
+    (r"(?i)\(radio edit\)", ""),
+    (r"(?i)\[radio edit\]", ""),
+];
+
+#[derive(Debug, Clone)]
+enum Action {
+    Replace(String),
+    Feature,
+}
+
+// How track metadata is cleaned up before it is exported. Rule files have
+// one `key = value` rule per line, `split` adds an artist separator,
+// `nosplit` removes one and `keep` names an artist that is never split.
+// Titles and albums are left alone unless `clean_titles = on` turns on the
+// built-in rules, or `title = <regex> => <replacement>` and `feature = <regex>`
+// add rules of their own. Replacements refer to groups as `$1` or `${name}`,
+// features take their artists from the first group.
+#[derive(Debug, Clone)]
+pub struct MetadataRules {
+    separators: Vec<String>,
+    exceptions: Vec<String>,
+    titles: Vec<(Regex, Action)>,
+}
+
+impl MetadataRules {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<MetadataRules> {
+        let mut rules = MetadataRules::default();
+
+        for (n, line) in std::fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let err =
+                |msg: &str| Error::new(ErrorKind::InvalidData, format!("line {}: {}", n + 1, msg));
+
+            let pos = line.find('=').ok_or_else(|| err("expected key = value"))?;
+            let value = line[pos + 1..].trim().to_string();
+            if value.is_empty() {
+                return Err(err("missing value"));
+            }
+
+            match line[..pos].trim() {
+                "split" => rules.separators.push(value),
+                "nosplit" => rules
+                    .separators
+                    .retain(|separator| !separator.eq_ignore_ascii_case(&value)),
+                "keep" => rules.exceptions.push(value),
+                "clean_titles" => match value.as_str() {
+                    "on" => rules.titles.extend(builtin_titles()),
+                    "off" => {}
+                    _ => return Err(err("expected on or off")),
+                },
+                "title" => {
+                    let pos = value
+                        .find("=>")
+                        .ok_or_else(|| err("expected pattern => replacement"))?;
+                    let pattern = Regex::new(value[..pos].trim())
+                        .map_err(|e| err(&format!("invalid pattern: {}", e)))?;
+                    rules.titles.push((
+                        pattern,
+                        Action::Replace(value[pos + 2..].trim().to_string()),
+                    ));
+                }
+                "feature" => {
+                    let pattern =
+                        Regex::new(&value).map_err(|e| err(&format!("invalid pattern: {}", e)))?;
+                    rules.titles.push((pattern, Action::Feature));
+                }
+                key => return Err(err(&format!("unknown rule `{}`", key))),
+            }
+        }
+
+        Ok(rules)
+    }
+
+    // "A, B feat. C" becomes A, B and C.
+    pub fn split_artists(&self, artist: &str) -> Vec<String> {
+        let mut artists = Vec::new();
+        let mut current = String::new();
+        let mut at = 0;
+
+        while let Some(c) = artist[at..].chars().next() {
+            if let Some(name) = self
+                .exceptions
+                .iter()
+                .find(|name| matches_at(artist, at, name, false))
+            {
+                current.push_str(&artist[at..at + name.len()]);
+                at += name.len();
+            } else if let Some(separator) = self
+                .separators
+                .iter()
+                .find(|separator| matches_at(artist, at, separator, true))
+            {
+                push_artist(&mut artists, &current);
+                current.clear();
+                at += separator.len();
+            } else {
+                current.push(c);
+                at += c.len_utf8();
+            }
+        }
+
+        push_artist(&mut artists, &current);
+        artists
+    }
+
+    // Applies the title rules to a name or album, featured artists are added
+    // to `artists`.
+    pub fn clean_title(&self, title: &str, artists: &mut Vec<String>) -> String {
+        if self.titles.is_empty() {
+            return title.to_string();
+        }
+
+        let mut title = title.to_string();
+
+        for (pattern, action) in &self.titles {
+            title = match action {
+                Action::Replace(replacement) => pattern.replace_all(&title, replacement.as_str()),
+                Action::Feature => pattern.replace_all(&title, |captures: &Captures| {
+                    let featured = captures.get(1).or_else(|| captures.get(0));
+                    if let Some(featured) = featured {
+                        for artist in self.split_artists(featured.as_str()) {
+                            push_artist(artists, &artist);
+                        }
+                    }
+                    String::new()
+                }),
+            }
+            .into_owned();
+        }
+
+        title.split_whitespace().collect::<Vec<_>>().join(" ")
+    }
+}
+
+fn builtin_titles() -> Vec<(Regex, Action)> {
+    let features = FEATURES
+        .iter()
+        .map(|pattern| (Regex::new(pattern).unwrap(), Action::Feature));
+    let replacements = REPLACEMENTS.iter().map(|(pattern, replacement)| {
+        (
+            Regex::new(pattern).unwrap(),
+            Action::Replace(replacement.to_string()),
+        )
+    });
+
+    features.chain(replacements).collect()
+}
+
+impl Default for MetadataRules {
+    fn default() -> Self {
+        MetadataRules {
+            separators: SEPARATORS.iter().map(|s| s.to_string()).collect(),
+            exceptions: EXCEPTIONS.iter().map(|s| s.to_string()).collect(),
+            titles: Vec::new(),
+        }
+    }
+}
+
+// Matches ignore case. Tokens that start or end with a letter only match
+// whole words, and a separator like `x` needs spaces around it, so neither
+// "Lil Nas X" nor "Xzibit" are split.
+fn matches_at(text: &str, at: usize, token: &str, separator: bool) -> bool {
+    match text.get(at..at + token.len()) {
+        Some(candidate) if !token.is_empty() && candidate.eq_ignore_ascii_case(token) => {}
+        _ => return false,
+    }
+
+    let bounded = |c: Option<char>| match c {
+        Some(c) if separator => c.is_whitespace(),
+        Some(c) => !c.is_alphanumeric(),
+        None => !separator,
+    };
+
+    let before = text[..at].chars().next_back();
+    let after = text[at + token.len()..].chars().next();
+
+    (!token.starts_with(char::is_alphanumeric) || bounded(before))
+        && (!token.ends_with(char::is_alphanumeric) || bounded(after))
+}
+
+fn push_artist(artists: &mut Vec<String>, artist: &str) {
+    let artist = artist.trim();
+    if !artist.is_empty() && !artists.iter().any(|a| a == artist) {
+        artists.push(artist.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn rules(text: &str) -> io::Result<MetadataRules> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+        let path = std::env::temp_dir().join(format!(
+            "bridge-rules-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::write(&path, text)?;
+        let rules = MetadataRules::open(&path);
+        std::fs::remove_file(&path)?;
+        rules
+    }
+
+    fn clean(rules: &MetadataRules, title: &str) -> (String, Vec<String>) {
+        let mut artists = vec!["Artist".to_string()];
+        let title = rules.clean_title(title, &mut artists);
+        (title, artists)
+    }
+
+    #[test]
+    fn anchored_rules_apply_once() {
+        let rules = rules(r"title = ^\d+ - =>").unwrap();
+        assert_eq!(clean(&rules, "01 - 1999 - Remix").0, "1999 - Remix");
+        assert_eq!(clean(&rules, "Song 02 - 03 - Mix").0, "Song 02 - 03 - Mix");
+    }
+
+    #[test]
+    fn replacements_use_groups() {
+        let rules = rules(r"title = (?i)^(.+) \(live\)$ => $1 [Live]").unwrap();
+        assert_eq!(clean(&rules, "Song (LIVE)").0, "Song [Live]");
+        assert_eq!(clean(&rules, "Song (Live) Edit").0, "Song (Live) Edit");
+    }
+
+    #[test]
+    fn builtin_rules() {
+        let rules = rules("clean_titles = on").unwrap();
+
+        assert_eq!(
+            clean(&rules, "Song (feat. A & B) - 2011 Remaster"),
+            (
+                "Song".to_string(),
+                vec!["Artist".to_string(), "A".to_string(), "B".to_string()]
+            )
+        );
+        assert_eq!(
+            clean(&rules, "Song - Live - Remastered 2009").0,
+            "Song - Live"
+        );
+        assert_eq!(clean(&rules, "Song [Radio Edit]").0, "Song");
+        assert_eq!(clean(&rules, "Song (with Artist)").1, vec!["Artist"]);
+        assert_eq!(clean(&rules, "Remastered").0, "Remastered");
+    }
+
+    #[test]
+    fn feature_rules_take_the_first_group() {
+        let rules = rules(r"feature = (?i) w/ (.+)$").unwrap();
+        assert_eq!(
+            clean(&rules, "Song w/ Hall & Oates, C"),
+            (
+                "Song".to_string(),
+                vec![
+                    "Artist".to_string(),
+                    "Hall & Oates".to_string(),
+                    "C".to_string()
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn invalid_patterns_are_reported() {
+        let err = rules("# titles\ntitle = (unclosed =>").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().starts_with("line 2: invalid pattern"));
+    }
+
+    #[test]
+    fn splits_artists_around_exceptions() {
+        let rules = MetadataRules::default();
+        assert_eq!(
+            rules.split_artists("Simon & Garfunkel, Lil Nas X x Xzibit feat. Hall & Oates"),
+            vec!["Simon & Garfunkel", "Lil Nas X", "Xzibit", "Hall & Oates"]
+        );
+    }
+}
@@ -0,0 +1,348 @@
+use crate::backend::PlayerTrack;
+use crate::error;
+use crate::status::{PlaybackStatus, RepeatMode};
+use std::io::{Error, ErrorKind, Result};
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrackSnapshot {
+    pub artist: Option<String>,
+    pub id: Option<String>,
+    pub name: Option<String>,
+    pub album: Option<String>,
+    pub album_artist: Option<String>,
+    pub artwork_url: Option<String>,
+    pub disk_number: Option<i32>,
+    pub duration: Option<i32>,
+    pub url: Option<String>,
+    pub track_number: Option<i32>,
+    pub explicit: Option<bool>,
+    pub popularity: Option<i32>,
+    pub played_count: Option<i32>,
+    pub starred: Option<bool>,
+    // Set by players that list artists one by one, they are taken as they are
+    // instead of splitting `artist` and `album_artist`.
+    pub artists: Vec<String>,
+    pub album_artists: Vec<String>,
+}
+
+impl TrackSnapshot {
+    pub fn is_empty(&self) -> bool {
+        *self == TrackSnapshot::default()
+    }
+
+    pub fn read<T: PlayerTrack>(track: &T) -> error::Result<TrackSnapshot> {
+        Ok(TrackSnapshot {
+            artist: track.artist()?,
+            id: track.id()?,
+            name: track.name()?,
+            album: track.album()?,
+            album_artist: track.album_artist()?,
+            artwork_url: track.artwork_url()?,
+            disk_number: track.disk_number()?,
+            duration: track.duration()?,
+            url: track.url()?,
+            track_number: track.track_number()?,
+            explicit: track.explicit()?,
+            popularity: track.popularity()?,
+            played_count: track.played_count()?,
+            starred: track.starred()?,
+            ..Default::default()
+        })
+    }
+
+    pub fn decode(line: &str) -> Result<TrackSnapshot> {
+        let mut track = TrackSnapshot::default();
+
+        for (key, value) in fields(line)? {
+            if !track.decode_field(&key, value)? {
+                return Err(invalid(format!("unknown track field `{}`", key)));
+            }
+        }
+
+        Ok(track)
+    }
+
+    fn decode_field(&mut self, key: &str, value: String) -> Result<bool> {
+        match key {
+            "artist" => self.artist = Some(value),
+            "id" => self.id = Some(value),
+            "name" => self.name = Some(value),
+            "album" => self.album = Some(value),
+            "album_artist" => self.album_artist = Some(value),
+            "artwork_url" => self.artwork_url = Some(value),
+            "disk_number" => self.disk_number = Some(parse(key, &value)?),
+            "duration" => self.duration = Some(parse(key, &value)?),
+            "url" => self.url = Some(value),
+            "track_number" => self.track_number = Some(parse(key, &value)?),
+            "explicit" => self.explicit = Some(parse(key, &value)?),
+            "popularity" => self.popularity = Some(parse(key, &value)?),
+            "played_count" => self.played_count = Some(parse(key, &value)?),
+            "starred" => self.starred = Some(parse(key, &value)?),
+            "artists" => self.artists.push(value),
+            "album_artists" => self.album_artists.push(value),
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    fn encode_into(&self, out: &mut String) {
+        field(out, "artist", &self.artist);
+        field(out, "id", &self.id);
+        field(out, "name", &self.name);
+        field(out, "album", &self.album);
+        field(out, "album_artist", &self.album_artist);
+        field(out, "artwork_url", &self.artwork_url);
+        field(out, "disk_number", &self.disk_number);
+        field(out, "duration", &self.duration);
+        field(out, "url", &self.url);
+        field(out, "track_number", &self.track_number);
+        field(out, "explicit", &self.explicit);
+        field(out, "popularity", &self.popularity);
+        field(out, "played_count", &self.played_count);
+        field(out, "starred", &self.starred);
+        for artist in &self.artists {
+            field(out, "artists", &Some(artist));
+        }
+        for artist in &self.album_artists {
+            field(out, "album_artists", &Some(artist));
+        }
+    }
+}
+
+impl PlayerTrack for TrackSnapshot {
+    fn artist(&self) -> error::Result<Option<String>> {
+        Ok(self.artist.clone())
+    }
+
+    fn id(&self) -> error::Result<Option<String>> {
+        Ok(self.id.clone())
+    }
+
+    fn name(&self) -> error::Result<Option<String>> {
+        Ok(self.name.clone())
+    }
+
+    fn album(&self) -> error::Result<Option<String>> {
+        Ok(self.album.clone())
+    }
+
+    fn album_artist(&self) -> error::Result<Option<String>> {
+        Ok(self.album_artist.clone())
+    }
+
+    fn artwork_url(&self) -> error::Result<Option<String>> {
+        Ok(self.artwork_url.clone())
+    }
+
+    fn disk_number(&self) -> error::Result<Option<i32>> {
+        Ok(self.disk_number)
+    }
+
+    fn duration(&self) -> error::Result<Option<i32>> {
+        Ok(self.duration)
+    }
+
+    fn url(&self) -> error::Result<Option<String>> {
+        Ok(self.url.clone())
+    }
+
+    fn track_number(&self) -> error::Result<Option<i32>> {
+        Ok(self.track_number)
+    }
+
+    fn explicit(&self) -> error::Result<Option<bool>> {
+        Ok(self.explicit)
+    }
+
+    fn popularity(&self) -> error::Result<Option<i32>> {
+        Ok(self.popularity)
+    }
+
+    fn played_count(&self) -> error::Result<Option<i32>> {
+        Ok(self.played_count)
+    }
+
+    fn starred(&self) -> error::Result<Option<bool>> {
+        Ok(self.starred)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatusSnapshot {
+    pub playback_status: PlaybackStatus,
+    pub shuffling: Option<bool>,
+    pub repeat_mode: Option<RepeatMode>,
+    pub position: Option<f64>,
+    pub volume: Option<i32>,
+    pub track: TrackSnapshot,
+}
+
+impl Default for StatusSnapshot {
+    fn default() -> Self {
+        StatusSnapshot {
+            playback_status: PlaybackStatus::Stopped,
+            shuffling: None,
+            repeat_mode: None,
+            position: None,
+            volume: None,
+            track: Default::default(),
+        }
+    }
+}
+
+impl StatusSnapshot {
+    // One line of `key=value` pairs separated by `;`, missing values are omitted.
+    pub fn encode(&self) -> String {
+        let mut out = String::new();
+
+        field(&mut out, "status", &Some(self.playback_status.as_str()));
+        field(&mut out, "shuffling", &self.shuffling);
+        field(&mut out, "repeat", &self.repeat_mode);
+        field(&mut out, "position", &self.position);
+        field(&mut out, "volume", &self.volume);
+        self.track.encode_into(&mut out);
+
+        out
+    }
+
+    pub fn decode(line: &str) -> Result<StatusSnapshot> {
+        StatusSnapshot::from_fields(fields(line)?)
+    }
+
+    pub fn from_fields<I: IntoIterator<Item = (String, String)>>(
+        fields: I,
+    ) -> Result<StatusSnapshot> {
+        let mut snapshot = StatusSnapshot::default();
+
+        for (key, value) in fields {
+            match key.as_str() {
+                "status" => snapshot.playback_status = parse(&key, &value)?,
+                "shuffling" => snapshot.shuffling = Some(parse(&key, &value)?),
+                "repeat" => snapshot.repeat_mode = Some(parse(&key, &value)?),
+                // Written before track repeat was known.
+                "repeating" => {
+                    snapshot.repeat_mode = Some(RepeatMode::from(parse::<bool>(&key, &value)?))
+                }
+                "position" => snapshot.position = Some(parse(&key, &value)?),
+                "volume" => snapshot.volume = Some(parse(&key, &value)?),
+                // Fields from newer bridges are skipped, so a server and its
+                // clients can be updated one at a time.
+                _ => {
+                    snapshot.track.decode_field(&key, value)?;
+                }
+            }
+        }
+
+        Ok(snapshot)
+    }
+}
+
+fn invalid(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+fn parse<T: std::str::FromStr>(key: &str, value: &str) -> Result<T> {
+    value
+        .parse()
+        .map_err(|_| invalid(format!("invalid value `{}` for `{}`", value, key)))
+}
+
+fn field<T: std::fmt::Display>(out: &mut String, key: &str, value: &Option<T>) {
+    if let Some(value) = value {
+        if !out.is_empty() {
+            out.push(';');
+        }
+
+        out.push_str(key);
+        out.push('=');
+        for c in value.to_string().chars() {
+            match c {
+                '\\' => out.push_str("\\\\"),
+                ';' => out.push_str("\\;"),
+                '\n' => out.push_str("\\n"),
+                '\r' => out.push_str("\\r"),
+                c => out.push(c),
+            }
+        }
+    }
+}
+
+fn fields(line: &str) -> Result<Vec<(String, String)>> {
+    let mut res = Vec::new();
+    let mut current = String::new();
+    let mut chars = line.trim_end_matches(&['\r', '\n'][..]).chars();
+
+    loop {
+        let c = chars.next();
+        match c {
+            Some('\\') => match chars.next() {
+                Some('n') => current.push('\n'),
+                Some('r') => current.push('\r'),
+                Some(c) => current.push(c),
+                None => return Err(invalid("dangling escape".to_string())),
+            },
+            Some(';') | None => {
+                if !current.trim().is_empty() {
+                    let pos = current
+                        .find('=')
+                        .ok_or_else(|| invalid(format!("expected key=value, got `{}`", current)))?;
+                    res.push((
+                        current[..pos].trim().to_string(),
+                        current[pos + 1..].to_string(),
+                    ));
+                }
+                current.clear();
+
+                if c.is_none() {
+                    break;
+                }
+            }
+            Some(c) => current.push(c),
+        }
+    }
+
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MockBackend;
+    use crate::rules::MetadataRules;
+    use crate::AppState;
+
+    #[test]
+    fn unknown_status_fields_are_skipped() {
+        let snapshot = StatusSnapshot::decode("status=playing;mood=happy;name=Song").unwrap();
+        assert_eq!(snapshot.playback_status, PlaybackStatus::Playing);
+        assert_eq!(snapshot.track.name.as_deref(), Some("Song"));
+
+        assert!(StatusSnapshot::decode("status=playing;volume=loud").is_err());
+        assert!(TrackSnapshot::decode("name=Song;mood=happy").is_err());
+    }
+
+    #[test]
+    fn status_snapshots_keep_every_artist() {
+        let state = AppState::new(
+            MockBackend::with_tracks(vec![TrackSnapshot {
+                id: Some("spotify:track:4uLU6hMCjMI75M1A2tKUQC".to_string()),
+                name: Some("Under Pressure".to_string()),
+                artists: vec!["Queen".to_string(), "David Bowie".to_string()],
+                album_artists: vec!["Queen".to_string()],
+                ..Default::default()
+            }]),
+            None,
+            MetadataRules::default(),
+        );
+        state.update().unwrap();
+
+        let snapshot = state.spotify_status().snapshot();
+        assert_eq!(snapshot.track.artists, ["Queen", "David Bowie"]);
+        assert_eq!(snapshot.track.album_artists, ["Queen"]);
+        assert_eq!(
+            StatusSnapshot::decode(&snapshot.encode()).unwrap(),
+            snapshot
+        );
+    }
+}
@@ -1,8 +1,99 @@
+use crate::backend::PlayerBackend;
+use crate::cache::{CacheStats, MetadataCache};
+use crate::capabilities::Capabilities;
+use crate::error::{self, BridgeError};
+use crate::rules::MetadataRules;
+use crate::snapshot::{StatusSnapshot, TrackSnapshot};
+use crate::uri::SpotifyUri;
 use crate::util::ATracked;
-pub use macos_spotify::{Spotify as SpotifyClient, State as PlaybackStatus};
 use std::fmt;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+// How far the position may drift from the expected one between two polls
+// before it counts as a seek, in seconds.
+const SEEK_TOLERANCE: f64 = 1.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaybackStatus {
+    Stopped,
+    Playing,
+    Paused,
+}
+
+impl PlaybackStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PlaybackStatus::Stopped => "stopped",
+            PlaybackStatus::Playing => "playing",
+            PlaybackStatus::Paused => "paused",
+        }
+    }
+}
+
+impl std::str::FromStr for PlaybackStatus {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stopped" => Ok(PlaybackStatus::Stopped),
+            "playing" => Ok(PlaybackStatus::Playing),
+            "paused" => Ok(PlaybackStatus::Paused),
+            _ => Err(()),
+        }
+    }
+}
+
+// Context repeats the album or playlist playing, track repeats the current track.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RepeatMode {
+    Off,
+    Context,
+    Track,
+}
 
+impl RepeatMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RepeatMode::Off => "off",
+            RepeatMode::Context => "context",
+            RepeatMode::Track => "track",
+        }
+    }
+}
+
+// Players that can only repeat or not repeat the context.
+impl From<bool> for RepeatMode {
+    fn from(repeating: bool) -> Self {
+        if repeating {
+            RepeatMode::Context
+        } else {
+            RepeatMode::Off
+        }
+    }
+}
+
+impl fmt::Display for RepeatMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for RepeatMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(RepeatMode::Off),
+            "context" => Ok(RepeatMode::Context),
+            "track" => Ok(RepeatMode::Track),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Default)]
 pub struct Track {
     artist: ATracked<Option<String>>,
     id: ATracked<Option<String>>,
@@ -13,22 +104,17 @@ pub struct Track {
     disk_number: ATracked<Option<i32>>,
     duration: ATracked<Option<i32>>,
     url: ATracked<Option<String>>,
-}
-
-impl Default for Track {
-    fn default() -> Self {
-        Track {
-            artist: Default::default(),
-            id: Default::default(),
-            name: Default::default(),
-            album: Default::default(),
-            album_artist: Default::default(),
-            artwork_url: Default::default(),
-            disk_number: Default::default(),
-            duration: Default::default(),
-            url: Default::default(),
-        }
-    }
+    track_number: ATracked<Option<i32>>,
+    explicit: ATracked<Option<bool>>,
+    popularity: ATracked<Option<i32>>,
+    played_count: ATracked<Option<i32>>,
+    starred: ATracked<Option<bool>>,
+    // The artist fields split into single artists, and name and album as
+    // cleaned up by the metadata rules.
+    artists: ATracked<Vec<String>>,
+    album_artists: ATracked<Vec<String>>,
+    clean_name: ATracked<Option<String>>,
+    clean_album: ATracked<Option<String>>,
 }
 
 impl Track {
@@ -48,6 +134,11 @@ impl Track {
         self.id.set(value)
     }
 
+    // The id as a Spotify URI, if it is one.
+    pub fn uri(&self) -> Option<SpotifyUri> {
+        self.id().as_deref().and_then(SpotifyUri::parse)
+    }
+
     pub fn name(&self) -> Arc<Option<String>> {
         self.name.get()
     }
@@ -104,6 +195,78 @@ impl Track {
         self.url.set(value)
     }
 
+    pub fn track_number(&self) -> Arc<Option<i32>> {
+        self.track_number.get()
+    }
+
+    pub fn set_track_number(&self, value: Option<i32>) {
+        self.track_number.set(value)
+    }
+
+    pub fn explicit(&self) -> Arc<Option<bool>> {
+        self.explicit.get()
+    }
+
+    pub fn set_explicit(&self, value: Option<bool>) {
+        self.explicit.set(value)
+    }
+
+    pub fn popularity(&self) -> Arc<Option<i32>> {
+        self.popularity.get()
+    }
+
+    pub fn set_popularity(&self, value: Option<i32>) {
+        self.popularity.set(value)
+    }
+
+    pub fn played_count(&self) -> Arc<Option<i32>> {
+        self.played_count.get()
+    }
+
+    pub fn set_played_count(&self, value: Option<i32>) {
+        self.played_count.set(value)
+    }
+
+    pub fn starred(&self) -> Arc<Option<bool>> {
+        self.starred.get()
+    }
+
+    pub fn set_starred(&self, value: Option<bool>) {
+        self.starred.set(value)
+    }
+
+    pub fn artists(&self) -> Arc<Vec<String>> {
+        self.artists.get()
+    }
+
+    pub fn set_artists(&self, value: Vec<String>) {
+        self.artists.set(value)
+    }
+
+    pub fn album_artists(&self) -> Arc<Vec<String>> {
+        self.album_artists.get()
+    }
+
+    pub fn set_album_artists(&self, value: Vec<String>) {
+        self.album_artists.set(value)
+    }
+
+    pub fn clean_name(&self) -> Arc<Option<String>> {
+        self.clean_name.get()
+    }
+
+    pub fn set_clean_name(&self, value: Option<String>) {
+        self.clean_name.set(value)
+    }
+
+    pub fn clean_album(&self) -> Arc<Option<String>> {
+        self.clean_album.get()
+    }
+
+    pub fn set_clean_album(&self, value: Option<String>) {
+        self.clean_album.set(value)
+    }
+
     pub fn has_changed(&self) -> bool {
         self.artist.has_changed()
             || self.id.has_changed()
@@ -114,6 +277,15 @@ impl Track {
             || self.disk_number.has_changed()
             || self.duration.has_changed()
             || self.url.has_changed()
+            || self.track_number.has_changed()
+            || self.explicit.has_changed()
+            || self.popularity.has_changed()
+            || self.played_count.has_changed()
+            || self.starred.has_changed()
+            || self.artists.has_changed()
+            || self.album_artists.has_changed()
+            || self.clean_name.has_changed()
+            || self.clean_album.has_changed()
     }
 
     pub fn reset(&self) {
@@ -126,6 +298,15 @@ impl Track {
         self.disk_number.reset();
         self.duration.reset();
         self.url.reset();
+        self.track_number.reset();
+        self.explicit.reset();
+        self.popularity.reset();
+        self.played_count.reset();
+        self.starred.reset();
+        self.artists.reset();
+        self.album_artists.reset();
+        self.clean_name.reset();
+        self.clean_album.reset();
     }
 }
 
@@ -141,6 +322,15 @@ impl fmt::Debug for Track {
             .field("disk_number", self.disk_number().as_ref())
             .field("duration", self.duration().as_ref())
             .field("url", self.url().as_ref())
+            .field("track_number", self.track_number().as_ref())
+            .field("explicit", self.explicit().as_ref())
+            .field("popularity", self.popularity().as_ref())
+            .field("played_count", self.played_count().as_ref())
+            .field("starred", self.starred().as_ref())
+            .field("artists", self.artists().as_ref())
+            .field("album_artists", self.album_artists().as_ref())
+            .field("clean_name", self.clean_name().as_ref())
+            .field("clean_album", self.clean_album().as_ref())
             .finish()
     }
 }
@@ -148,16 +338,59 @@ impl fmt::Debug for Track {
 unsafe impl Send for Track {}
 unsafe impl Sync for Track {}
 
+// Where the position was at the last poll, to predict where it should be now.
+#[derive(Debug, Clone, Copy)]
+struct PositionClock {
+    position: f64,
+    playing: bool,
+    at: Instant,
+}
+
+impl PositionClock {
+    fn expected(&self, now: Instant) -> f64 {
+        if self.playing {
+            self.position + (now - self.at).as_secs_f64()
+        } else {
+            self.position
+        }
+    }
+}
+
 pub struct SpotifyStatus {
     track: Arc<Track>,
     playback_status: ATracked<PlaybackStatus>,
     shuffling: ATracked<Option<bool>>,
-    repeating: ATracked<Option<bool>>,
+    repeat_mode: ATracked<Option<RepeatMode>>,
     position: ATracked<Option<f64>>,
     volume: ATracked<Option<i32>>,
+    capabilities: ATracked<Capabilities>,
+    metadata: Mutex<MetadataCache>,
+    clock: Mutex<Option<PositionClock>>,
+    seeked: AtomicBool,
+    // Set by Stop. Players only know paused, so the bridge reports stopped
+    // until playback resumes.
+    stopped: AtomicBool,
+    rules: MetadataRules,
 }
 
 impl SpotifyStatus {
+    pub fn new(rules: MetadataRules) -> SpotifyStatus {
+        SpotifyStatus {
+            track: Arc::new(Default::default()),
+            playback_status: ATracked::new(PlaybackStatus::Stopped),
+            shuffling: Default::default(),
+            repeat_mode: Default::default(),
+            position: Default::default(),
+            volume: Default::default(),
+            capabilities: ATracked::new(Capabilities::none()),
+            metadata: Default::default(),
+            clock: Mutex::new(None),
+            seeked: AtomicBool::new(false),
+            stopped: AtomicBool::new(false),
+            rules,
+        }
+    }
+
     pub fn playback_status(&self) -> PlaybackStatus {
         *self.playback_status.get()
     }
@@ -166,6 +399,11 @@ impl SpotifyStatus {
         self.playback_status.set(value);
     }
 
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        self.set_playback_status(PlaybackStatus::Stopped);
+    }
+
     pub fn is_shuffling(&self) -> Option<bool> {
         *self.shuffling.get()
     }
@@ -174,12 +412,12 @@ impl SpotifyStatus {
         self.shuffling.set(value);
     }
 
-    pub fn is_repeating(&self) -> Option<bool> {
-        *self.repeating.get()
+    pub fn repeat_mode(&self) -> Option<RepeatMode> {
+        *self.repeat_mode.get()
     }
 
-    pub fn set_repeating(&self, value: Option<bool>) {
-        self.repeating.set(value);
+    pub fn set_repeat_mode(&self, value: Option<RepeatMode>) {
+        self.repeat_mode.set(value);
     }
 
     pub fn position(&self) -> Option<f64> {
@@ -202,120 +440,237 @@ impl SpotifyStatus {
         self.track.clone()
     }
 
+    pub fn capabilities(&self) -> Capabilities {
+        *self.capabilities.get()
+    }
+
+    pub fn capabilities_changed(&self) -> bool {
+        self.capabilities.has_changed()
+    }
+
+    // Whether the position jumped since the last reset, rather than moving
+    // along with playback.
+    pub fn seeked(&self) -> bool {
+        self.seeked.load(Ordering::Relaxed)
+    }
+
+    pub fn snapshot(&self) -> StatusSnapshot {
+        let track = self.track();
+
+        StatusSnapshot {
+            playback_status: self.playback_status(),
+            shuffling: self.is_shuffling(),
+            repeat_mode: self.repeat_mode(),
+            position: self.position(),
+            volume: self.volume(),
+            track: TrackSnapshot {
+                artist: track.artist().as_ref().clone(),
+                id: track.id().as_ref().clone(),
+                name: track.name().as_ref().clone(),
+                album: track.album().as_ref().clone(),
+                album_artist: track.album_artist().as_ref().clone(),
+                artwork_url: track.artwork_url().as_ref().clone(),
+                disk_number: *track.disk_number(),
+                duration: *track.duration(),
+                url: track.url().as_ref().clone(),
+                track_number: *track.track_number(),
+                explicit: *track.explicit(),
+                popularity: *track.popularity(),
+                played_count: *track.played_count(),
+                starred: *track.starred(),
+                // Already split, so clients need no rules of their own.
+                artists: track.artists().as_ref().clone(),
+                album_artists: track.album_artists().as_ref().clone(),
+            },
+        }
+    }
+
     pub fn has_changed(&self) -> bool {
+        self.properties_changed() || self.seeked()
+    }
+
+    // The position is left out, it moves on every poll while playing.
+    pub fn properties_changed(&self) -> bool {
         self.track.has_changed()
             || self.playback_status.has_changed()
             || self.shuffling.has_changed()
-            || self.repeating.has_changed()
-            || self.position.has_changed()
+            || self.repeat_mode.has_changed()
             || self.volume.has_changed()
+            || self.capabilities.has_changed()
     }
 
     pub fn reset(&self) {
         self.track.reset();
         self.playback_status.reset();
         self.shuffling.reset();
-        self.repeating.reset();
+        self.repeat_mode.reset();
         self.position.reset();
         self.volume.reset();
+        self.capabilities.reset();
+        self.seeked.store(false, Ordering::Relaxed);
     }
 
-    pub fn update(&self, spotify: &SpotifyClient) -> std::io::Result<()> {
+    pub fn update<B: PlayerBackend>(&self, spotify: &B) -> error::Result<()> {
         match self._update(spotify) {
-            Err(err) => {
-                if let Some(code) = err.raw_os_error() {
-                    if code == -600 || code == -609 {
-                        self.set_playback_status(PlaybackStatus::STOPPED);
-                        self.set_volume(None);
-                        self.set_shuffling(None);
-                        self.set_repeating(None);
-                        self.set_position(None);
-
-                        let track = self.track();
-
-                        track.set_artist(None);
-                        track.set_id(None);
-                        track.set_name(None);
-                        track.set_album(None);
-                        track.set_album_artist(None);
-                        track.set_artwork_url(None);
-                        track.set_disk_number(None);
-                        track.set_duration(None);
-                        track.set_url(None);
-
-                        Ok(())
-                    } else {
-                        Err(err)
-                    }
-                } else {
-                    Err(err)
-                }
+            Err(BridgeError::NotRunning) => {
+                self.not_running(spotify);
+                Ok(())
+            }
+            res => {
+                let has_track = self.track.id().is_some() || self.track.name().is_some();
+                self.capabilities
+                    .set(spotify.capabilities().available(true, has_track));
+                res
             }
-            Ok(()) => Ok(()),
         }
     }
 
-    fn _update(&self, spotify: &SpotifyClient) -> std::io::Result<()> {
-        let playback_status = spotify.state()?.unwrap_or(PlaybackStatus::STOPPED);
-        let volume;
-        let mut shuffling = None;
-        let mut repeating = None;
-        let mut position = None;
-
-        let mut artist = None;
-        let mut id = None;
-        let mut name = None;
-        let mut album = None;
-        let mut album_artist = None;
-        let mut artwork_url = None;
-        let mut disk_number = None;
-        let mut duration = None;
-        let mut url = None;
-
-        if playback_status != PlaybackStatus::STOPPED {
-            shuffling = spotify.is_shuffling()?;
-            repeating = spotify.is_repeating()?;
-            position = spotify.pos()?;
-            volume = spotify.volume()?;
-
-            let track = spotify.track()?;
-
-            if track.is_some() {
-                let track = track.unwrap();
-                artist = track.artist()?;
-                id = track.id()?;
-                name = track.name()?;
-                album = track.album()?;
-                album_artist = track.album_artist()?;
-                artwork_url = track.artwork_url()?;
-                disk_number = track.disk_number()?;
-                duration = track.duration()?;
-                url = track.url()?;
-            }
+    // Forgets the player, e.g. once it was asked to quit, without waiting for
+    // the next poll to notice.
+    pub fn not_running<B: PlayerBackend>(&self, spotify: &B) {
+        self.apply(StatusSnapshot::default());
+        *self.clock.lock().unwrap() = None;
+        self.stopped.store(false, Ordering::Relaxed);
+        self.metadata.lock().unwrap().invalidate();
+        self.capabilities
+            .set(spotify.capabilities().available(false, false));
+    }
+
+    // The next poll fetches the track metadata again even if the track did not change.
+    pub fn refresh_metadata(&self) {
+        self.metadata.lock().unwrap().invalidate();
+    }
+
+    pub fn metadata_stats(&self) -> CacheStats {
+        self.metadata.lock().unwrap().stats()
+    }
+
+    fn _update<B: PlayerBackend>(&self, spotify: &B) -> error::Result<()> {
+        // A track without an id cannot be cached, asking for the id first
+        // would only add round trips.
+        let uncached = self.track.id().is_none() && self.track.name().is_some();
+
+        let mut snapshot = if uncached {
+            spotify.snapshot()?
         } else {
-            volume = spotify.volume()?;
+            spotify.playback_snapshot()?
+        };
+
+        if snapshot.playback_status != PlaybackStatus::Stopped {
+            let cached = self.metadata.lock().unwrap().get(&snapshot.track.id);
+            match cached {
+                // These change while the id stays, the poll has the latest.
+                Some(track) => {
+                    snapshot.track = TrackSnapshot {
+                        popularity: snapshot.track.popularity,
+                        played_count: snapshot.track.played_count,
+                        starred: snapshot.track.starred,
+                        ..track
+                    }
+                }
+                None if uncached => self.metadata.lock().unwrap().store(snapshot.track.clone()),
+                None => {
+                    snapshot = spotify.snapshot()?;
+                    self.metadata.lock().unwrap().store(snapshot.track.clone());
+                }
+            }
         }
 
-        self.set_playback_status(playback_status);
-        self.set_volume(volume);
-        self.set_shuffling(shuffling);
-        self.set_repeating(repeating);
-        self.set_position(position);
+        if snapshot.playback_status == PlaybackStatus::Stopped {
+            // Nothing but the volume means anything while stopped.
+            snapshot = StatusSnapshot {
+                volume: snapshot.volume,
+                ..Default::default()
+            };
+        }
 
-        let track = self.track();
+        match snapshot.playback_status {
+            PlaybackStatus::Paused if self.stopped.load(Ordering::Relaxed) => {
+                snapshot.playback_status = PlaybackStatus::Stopped;
+            }
+            PlaybackStatus::Paused => {}
+            _ => self.stopped.store(false, Ordering::Relaxed),
+        }
 
-        track.set_artist(artist);
-        track.set_id(id);
-        track.set_name(name);
-        track.set_album(album);
-        track.set_album_artist(album_artist);
-        track.set_artwork_url(artwork_url);
-        track.set_disk_number(disk_number);
-        track.set_duration(duration);
-        track.set_url(url);
+        self.follow_position(&snapshot);
+        self.apply(snapshot);
 
         Ok(())
     }
+
+    fn follow_position(&self, snapshot: &StatusSnapshot) {
+        let now = Instant::now();
+        let mut clock = self.clock.lock().unwrap();
+
+        if let (Some(previous), Some(position)) = (*clock, snapshot.position) {
+            // A new track starting from the top is no seek. Tracks without an
+            // id are told apart by their name and length.
+            let same_track = snapshot.track.id == *self.track.id()
+                && (snapshot.track.id.is_some()
+                    || (snapshot.track.name == *self.track.name()
+                        && snapshot.track.duration == *self.track.duration()));
+            if same_track && (position - previous.expected(now)).abs() > SEEK_TOLERANCE {
+                self.seeked.store(true, Ordering::Relaxed);
+            }
+        }
+
+        *clock = snapshot.position.map(|position| PositionClock {
+            position,
+            playing: snapshot.playback_status == PlaybackStatus::Playing,
+            at: now,
+        });
+    }
+
+    fn apply(&self, snapshot: StatusSnapshot) {
+        self.set_playback_status(snapshot.playback_status);
+        self.set_volume(snapshot.volume);
+        self.set_shuffling(snapshot.shuffling);
+        self.set_repeat_mode(snapshot.repeat_mode);
+        self.set_position(snapshot.position);
+
+        let track = self.track();
+        let split = |artists: &[String], artist: &Option<String>| {
+            if !artists.is_empty() {
+                return artists.to_vec();
+            }
+            artist
+                .as_deref()
+                .map(|artist| self.rules.split_artists(artist))
+                .unwrap_or_default()
+        };
+
+        let mut artists = split(&snapshot.track.artists, &snapshot.track.artist);
+        let mut album_artists = split(&snapshot.track.album_artists, &snapshot.track.album_artist);
+        let name = snapshot
+            .track
+            .name
+            .as_deref()
+            .map(|name| self.rules.clean_title(name, &mut artists));
+        let album = snapshot
+            .track
+            .album
+            .as_deref()
+            .map(|album| self.rules.clean_title(album, &mut album_artists));
+
+        track.set_artists(artists);
+        track.set_album_artists(album_artists);
+        track.set_clean_name(name);
+        track.set_clean_album(album);
+        track.set_artist(snapshot.track.artist);
+        track.set_id(snapshot.track.id);
+        track.set_name(snapshot.track.name);
+        track.set_album(snapshot.track.album);
+        track.set_album_artist(snapshot.track.album_artist);
+        track.set_artwork_url(snapshot.track.artwork_url);
+        track.set_disk_number(snapshot.track.disk_number);
+        track.set_duration(snapshot.track.duration);
+        track.set_url(snapshot.track.url);
+        track.set_track_number(snapshot.track.track_number);
+        track.set_explicit(snapshot.track.explicit);
+        track.set_popularity(snapshot.track.popularity);
+        track.set_played_count(snapshot.track.played_count);
+        track.set_starred(snapshot.track.starred);
+    }
 }
 
 impl fmt::Debug for SpotifyStatus {
@@ -324,23 +679,17 @@ impl fmt::Debug for SpotifyStatus {
             .field("track", self.track().as_ref())
             .field("playback_status", &self.playback_status())
             .field("shuffling", &self.is_shuffling().as_ref())
-            .field("repeating", &self.is_repeating().as_ref())
+            .field("repeat_mode", &self.repeat_mode().as_ref())
             .field("position", &self.position().as_ref())
             .field("volume", &self.volume().as_ref())
+            .field("capabilities", &self.capabilities())
             .finish()
     }
 }
 
 impl Default for SpotifyStatus {
     fn default() -> Self {
-        SpotifyStatus {
-            track: Arc::new(Default::default()),
-            playback_status: ATracked::new(PlaybackStatus::STOPPED),
-            shuffling: Default::default(),
-            repeating: Default::default(),
-            position: Default::default(),
-            volume: Default::default(),
-        }
+        SpotifyStatus::new(MetadataRules::default())
     }
 }
 
@@ -0,0 +1,292 @@
+use std::fmt;
+
+const URL_PREFIX: &str = "https://open.spotify.com/";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UriKind {
+    Track,
+    Album,
+    Artist,
+    Playlist,
+    Episode,
+    Show,
+    Local,
+    Ad,
+}
+
+const KINDS: &[(UriKind, &str)] = &[
+    (UriKind::Track, "track"),
+    (UriKind::Album, "album"),
+    (UriKind::Artist, "artist"),
+    (UriKind::Playlist, "playlist"),
+    (UriKind::Episode, "episode"),
+    (UriKind::Show, "show"),
+    (UriKind::Local, "local"),
+    (UriKind::Ad, "ad"),
+];
+
+impl UriKind {
+    pub fn as_str(self) -> &'static str {
+        KINDS.iter().find(|(kind, _)| *kind == self).unwrap().1
+    }
+
+    fn from_name(name: &str) -> Option<UriKind> {
+        KINDS
+            .iter()
+            .find(|(_, n)| *n == name)
+            .map(|(kind, _)| *kind)
+    }
+}
+
+// Names anything Spotify can link to, like `spotify:track:<id>` or
+// https://open.spotify.com/track/<id>. Ids are base62, except for local files
+// whose id is artist, album, title and duration joined by `:`. Old playlists
+// also name the user who made them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpotifyUri {
+    pub kind: UriKind,
+    pub id: String,
+    pub user: Option<String>,
+}
+
+impl SpotifyUri {
+    pub fn parse(s: &str) -> Option<SpotifyUri> {
+        s.parse().ok()
+    }
+
+    pub fn url(&self) -> String {
+        let mut url = URL_PREFIX.to_string();
+
+        if let Some(ref user) = self.user {
+            url.push_str("user/");
+            url.push_str(user);
+            url.push('/');
+        }
+
+        url.push_str(self.kind.as_str());
+        url.push('/');
+        url.push_str(&self.id.replace(':', "/"));
+        url
+    }
+
+    fn from_segments(segments: &[&str]) -> Result<SpotifyUri, String> {
+        let (user, segments) = match segments {
+            ["user", user, rest @ ..] if !rest.is_empty() && is_name(user) => {
+                (Some(user.to_string()), rest)
+            }
+            segments => (None, segments),
+        };
+
+        let (kind, id) = match segments {
+            [kind, id @ ..] if !id.is_empty() => (*kind, id),
+            _ => return Err("missing id".to_string()),
+        };
+
+        let kind = UriKind::from_name(kind).ok_or_else(|| format!("unknown kind `{}`", kind))?;
+
+        if user.is_some() && kind != UriKind::Playlist {
+            return Err(format!("a user has no {}", kind.as_str()));
+        }
+
+        let valid = match (kind, id) {
+            (UriKind::Local, id) => {
+                id.len() == 4
+                    && id.iter().all(|part| part.chars().all(is_encoded))
+                    && !id[3].is_empty()
+                    && id[3].chars().all(|c| c.is_ascii_digit())
+            }
+            (UriKind::Ad, [id]) => !id.is_empty() && id.chars().all(|c| c.is_ascii_hexdigit()),
+            (_, [id]) => id.len() == 22 && id.chars().all(|c| c.is_ascii_alphanumeric()),
+            _ => false,
+        };
+
+        if !valid {
+            return Err(format!("invalid {} id `{}`", kind.as_str(), id.join(":")));
+        }
+
+        Ok(SpotifyUri {
+            kind,
+            id: id.join(":"),
+            user,
+        })
+    }
+}
+
+fn is_name(s: &str) -> bool {
+    !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_' || c == '-')
+}
+
+// Local file names come form encoded, `+` for spaces and `%XX` for the rest.
+fn is_encoded(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "%+.-_".contains(c)
+}
+
+impl fmt::Display for SpotifyUri {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "spotify:")?;
+        if let Some(ref user) = self.user {
+            write!(f, "user:{}:", user)?;
+        }
+        write!(f, "{}:{}", self.kind.as_str(), self.id)
+    }
+}
+
+impl std::str::FromStr for SpotifyUri {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(rest) = s.strip_prefix("spotify:") {
+            return SpotifyUri::from_segments(&rest.split(':').collect::<Vec<_>>());
+        }
+
+        let rest = s
+            .strip_prefix(URL_PREFIX)
+            .ok_or_else(|| format!("not a Spotify URI or link `{}`", s))?;
+
+        let path = rest.split(&['?', '#'][..]).next().unwrap_or_default();
+        // Local files may lack an artist or album, so only the slashes around
+        // the path are dropped.
+        let mut segments = path.trim_matches('/').split('/').collect::<Vec<_>>();
+
+        // Links may name a language or be meant for embedding.
+        if segments
+            .first()
+            .is_some_and(|first| first.starts_with("intl-") || *first == "embed")
+        {
+            segments.remove(0);
+        }
+
+        SpotifyUri::from_segments(&segments)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: &str = "4uLU6hMCjMI75M1A2tKUQC";
+
+    fn parse(s: &str) -> SpotifyUri {
+        s.parse().unwrap_or_else(|err| panic!("{}: {}", s, err))
+    }
+
+    #[test]
+    fn parses_every_kind() {
+        for &(kind, name) in KINDS {
+            let id = match kind {
+                UriKind::Local => "Rick+Astley:Whenever+You+Need+Somebody:Never+Gonna:213",
+                UriKind::Ad => "0a1b2c3d",
+                _ => ID,
+            };
+
+            let uri = parse(&format!("spotify:{}:{}", name, id));
+            assert_eq!(uri.kind, kind);
+            assert_eq!(uri.kind.as_str(), name);
+            assert_eq!(uri.id, id);
+            assert_eq!(uri.user, None);
+        }
+    }
+
+    #[test]
+    fn parses_user_playlists() {
+        let uri = parse(&format!("spotify:user:spotify_user.1:playlist:{}", ID));
+        assert_eq!(uri.kind, UriKind::Playlist);
+        assert_eq!(uri.user.as_deref(), Some("spotify_user.1"));
+        assert_eq!(uri.id, ID);
+
+        let link = parse(&format!(
+            "https://open.spotify.com/user/spotify_user.1/playlist/{}",
+            ID
+        ));
+        assert_eq!(link, uri);
+
+        assert!(SpotifyUri::parse(&format!("spotify:user:someone:track:{}", ID)).is_none());
+        assert!(SpotifyUri::parse(&format!("spotify:user:some one:playlist:{}", ID)).is_none());
+        assert!(SpotifyUri::parse("spotify:user:someone").is_none());
+    }
+
+    #[test]
+    fn parses_local_files() {
+        let uri = parse("spotify:local:Artist:Album:Some+Title%21:180");
+        assert_eq!(uri.kind, UriKind::Local);
+        assert_eq!(uri.id, "Artist:Album:Some+Title%21:180");
+        assert_eq!(
+            uri.url(),
+            "https://open.spotify.com/local/Artist/Album/Some+Title%21/180"
+        );
+
+        let link = parse("https://open.spotify.com/local/Artist/Album/Some+Title%21/180");
+        assert_eq!(link, uri);
+
+        // Artist and album may be empty, the duration is a number.
+        assert!(SpotifyUri::parse("spotify:local:::Title:180").is_some());
+        assert!(SpotifyUri::parse("spotify:local:Artist:Album:Title:3m").is_none());
+        assert!(SpotifyUri::parse("spotify:local:Artist:Album:180").is_none());
+        assert!(SpotifyUri::parse("spotify:local:Artist:Album:Some Title:180").is_none());
+    }
+
+    #[test]
+    fn parses_links() {
+        let uri = parse(&format!("spotify:track:{}", ID));
+
+        for link in &[
+            format!("https://open.spotify.com/track/{}", ID),
+            format!("https://open.spotify.com/track/{}/", ID),
+            format!("https://open.spotify.com/track/{}?si=a1b2c3d4e5f6", ID),
+            format!("https://open.spotify.com/track/{}?si=a1b2#top", ID),
+            format!("https://open.spotify.com/intl-de/track/{}", ID),
+            format!("https://open.spotify.com/intl-pt-BR/track/{}?si=abc", ID),
+            format!("https://open.spotify.com/embed/track/{}", ID),
+        ] {
+            assert_eq!(parse(link), uri, "{}", link);
+        }
+
+        assert!(SpotifyUri::parse(&format!("http://open.spotify.com/track/{}", ID)).is_none());
+        assert!(SpotifyUri::parse(&format!("https://example.com/track/{}", ID)).is_none());
+        assert!(SpotifyUri::parse("https://open.spotify.com/track/").is_none());
+    }
+
+    #[test]
+    fn rejects_bad_ids() {
+        for uri in &[
+            "spotify:track:4uLU6hMCjMI75M1A2tKUQ",
+            "spotify:track:4uLU6hMCjMI75M1A2tKUQCC",
+            "spotify:track:4uLU6hMCjMI75M1A2tKUQ-",
+            "spotify:track:4uLU6hMCjMI75M1A2tKUQé",
+            "spotify:track:",
+            "spotify:track",
+            "spotify:ad:xyz",
+            "spotify:local:Artist:Album:Title:",
+            "spotify:song:4uLU6hMCjMI75M1A2tKUQC",
+            "spotify:track:4uLU6hMCjMI75M1A2tKUQC:extra",
+            "track:4uLU6hMCjMI75M1A2tKUQC",
+        ] {
+            assert!(SpotifyUri::parse(uri).is_none(), "{}", uri);
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        for s in &[
+            format!("spotify:track:{}", ID),
+            format!("spotify:album:{}", ID),
+            format!("spotify:user:someone:playlist:{}", ID),
+            "spotify:local:Artist:Album:Title:180".to_string(),
+            "spotify:local::Album:Title:180".to_string(),
+            "spotify:local:Artist::Title:180".to_string(),
+            "spotify:ad:0a1b2c3d".to_string(),
+        ] {
+            let uri = parse(s);
+            assert_eq!(uri.to_string(), *s);
+            assert_eq!(parse(&uri.to_string()), uri);
+            assert_eq!(parse(&uri.url()), uri);
+        }
+
+        assert_eq!(
+            parse(&format!("spotify:user:someone:playlist:{}", ID)).url(),
+            format!("https://open.spotify.com/user/someone/playlist/{}", ID)
+        );
+    }
+}
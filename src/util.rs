@@ -17,7 +17,7 @@ impl<T: PartialEq> ATracked<T> {
     }
 
     pub fn get(&self) -> Arc<T> {
-        (*self.inner.read().unwrap()).0.clone()
+        self.inner.read().unwrap().0.clone()
     }
 
     pub fn set(&self, value: T) {
@@ -32,11 +32,11 @@ impl<T: PartialEq> ATracked<T> {
 
     pub fn reset(&self) {
         let mut obj = self.inner.write().unwrap();
-        (*obj).1 = false;
+        obj.1 = false;
     }
 
     pub fn has_changed(&self) -> bool {
-        (*self.inner.read().unwrap()).1
+        self.inner.read().unwrap().1
     }
 }
 
@@ -55,5 +55,11 @@ impl<T: PartialEq> Default for ATracked<Option<T>> {
     }
 }
 
+impl<T: PartialEq> Default for ATracked<Vec<T>> {
+    fn default() -> Self {
+        ATracked::new(Vec::new())
+    }
+}
+
 unsafe impl<T: PartialEq> Send for ATracked<T> {}
 unsafe impl<T: PartialEq> Sync for ATracked<T> {}